@@ -0,0 +1,22 @@
+//! Compares reading the states of the standard lock keys at once with reading them one by one.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lock_keys::*;
+
+fn states(c: &mut Criterion) {
+    let lock_key = LockKey::new();
+    let mut group = c.benchmark_group("states");
+    group.bench_function("states", |b| b.iter(|| lock_key.states().unwrap()));
+    group.bench_function("state per key", |b| {
+        b.iter(|| {
+            LockKeys::all()
+                .into_iter()
+                .map(|key| lock_key.state_opt(key).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, states);
+criterion_main!(benches);
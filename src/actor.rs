@@ -0,0 +1,125 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::{LockKeyBuilder, LockKeyResult, LockKeyState, LockKeyWrapper, LockKeys};
+
+/// A command sent by the clients to the actor thread, with the channel receiving its result.
+#[derive(Debug)]
+enum Command {
+    Set(LockKeys, LockKeyState, Sender<LockKeyResult>),
+    Get(LockKeys, Sender<LockKeyResult>),
+    Toggle(LockKeys, Sender<LockKeyResult>),
+}
+
+/// A thread owning a lock key object, handling the commands of its clients one at a time.
+///
+/// Confining the OS handle to a single thread lets any thread handle the lock keys through the `Clone + Send`
+/// clients. The thread stops once the actor and all of its clients are dropped.
+#[derive(Debug)]
+pub struct LockKeyActor {
+    client: LockKeyClient,
+    thread: JoinHandle<()>,
+}
+
+impl LockKeyActor {
+    /// Spawns the actor thread, opening its lock key object with the builder options.
+    pub fn spawn(builder: LockKeyBuilder) -> io::Result<Self> {
+        Self::spawn_with(move || builder.build())
+    }
+
+    /// Spawns the actor thread owning the lock key object returned by `open`, failing with its error.
+    pub(crate) fn spawn_with<T, O>(open: O) -> io::Result<Self>
+    where
+        T: LockKeyWrapper,
+        O: FnOnce() -> io::Result<T> + Send + 'static,
+    {
+        let (commands, receiver) = mpsc::channel();
+        let (ready, ready_receiver) = mpsc::channel();
+        let thread = thread::Builder::new()
+            .name("lock_keys-actor".to_string())
+            .spawn(move || match open() {
+                Ok(lock_key) => {
+                    let _ = ready.send(Ok(()));
+                    run(&lock_key, receiver);
+                }
+                Err(err) => {
+                    let _ = ready.send(Err(err));
+                }
+            })?;
+        match ready_receiver.recv() {
+            Ok(Ok(())) => Ok(LockKeyActor {
+                client: LockKeyClient { commands },
+                thread,
+            }),
+            Ok(Err(err)) => {
+                let _ = thread.join();
+                Err(err)
+            }
+            Err(_) => {
+                let _ = thread.join();
+                Err(io::Error::other("lock key actor thread exited"))
+            }
+        }
+    }
+
+    /// Creates a client sending its commands to the actor.
+    pub fn client(&self) -> LockKeyClient {
+        self.client.clone()
+    }
+
+    /// Waits for the actor thread to finish, i.e. for all of its clients to be dropped.
+    pub fn join(self) {
+        drop(self.client);
+        let _ = self.thread.join();
+    }
+}
+
+/// Handles the commands until all the clients are dropped.
+///
+/// As the commands are handled one at a time, `Set` reads the state first to send nothing when it already matches,
+/// so concurrent identical `set` calls from the clients result in at most one backend event.
+fn run<T: LockKeyWrapper>(lock_key: &T, commands: Receiver<Command>) {
+    for command in commands {
+        let _ = match command {
+            Command::Set(key, state, reply) => reply.send(
+                lock_key
+                    .state(key)
+                    .and_then(|current| lock_key.set_known(key, current, state)),
+            ),
+            Command::Get(key, reply) => reply.send(lock_key.state(key)),
+            Command::Toggle(key, reply) => reply.send(lock_key.toggle(key)),
+        };
+    }
+}
+
+/// A handle sending commands to a `LockKeyActor`, which can be cloned and sent to other threads.
+#[derive(Clone, Debug)]
+pub struct LockKeyClient {
+    commands: Sender<Command>,
+}
+
+impl LockKeyClient {
+    /// Sets a new state for the lock key through the actor.
+    pub fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        self.request(|reply| Command::Set(key, state, reply))
+    }
+
+    /// Retrieves the lock key state through the actor.
+    pub fn state(&self, key: LockKeys) -> LockKeyResult {
+        self.request(|reply| Command::Get(key, reply))
+    }
+
+    /// Toggles the lock key state returning its previous state through the actor.
+    pub fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        self.request(|reply| Command::Toggle(key, reply))
+    }
+
+    /// Sends the command waiting for its result, failing with `BrokenPipe` when the actor thread is gone.
+    fn request(&self, command: impl FnOnce(Sender<LockKeyResult>) -> Command) -> LockKeyResult {
+        let (reply, receiver) = mpsc::channel();
+        let stopped = || io::Error::new(io::ErrorKind::BrokenPipe, "lock key actor stopped");
+        self.commands.send(command(reply)).map_err(|_| stopped())?;
+        receiver.recv().map_err(|_| stopped())?
+    }
+}
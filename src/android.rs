@@ -0,0 +1,62 @@
+use std::io;
+use std::ptr;
+
+use crate::{
+    unsupported_key, LockKey, LockKeyBuilder, LockKeyResult, LockKeyState, LockKeyWrapper,
+    LockKeys, NO_LOCK_KEYS_ON_ANDROID,
+};
+
+impl LockKey {
+    pub(crate) fn open(options: LockKeyBuilder) -> io::Result<Self> {
+        Ok(LockKey {
+            handle: ptr::null_mut(),
+            options,
+            trace: None,
+        })
+    }
+}
+
+/// A no-op backend letting cross-platform code build for Android, where lock keys are not meaningful: the lock key
+/// object is created, but every lock key is unsupported.
+impl LockKeyWrapper for LockKey {
+    /// Creates a new lock key object, which handles no lock key on Android.
+    fn new() -> Self {
+        LockKey {
+            handle: ptr::null_mut(),
+            options: LockKeyBuilder::default(),
+            trace: None,
+        }
+    }
+
+    /// Tries to create a new lock key object, which never fails on Android.
+    fn try_new() -> io::Result<Self> {
+        Ok(Self::new())
+    }
+
+    /// Fails with `Unsupported`, lock keys are not meaningful on Android.
+    fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        let result = Err(unsupported_key(key, NO_LOCK_KEYS_ON_ANDROID));
+        self.trace(format_args!("set({:?}, {})", key, state), result)
+    }
+
+    /// Fails with `Unsupported`, lock keys are not meaningful on Android.
+    fn enable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Enabled)
+    }
+
+    /// Fails with `Unsupported`, lock keys are not meaningful on Android.
+    fn disable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Disabled)
+    }
+
+    /// Fails with `Unsupported`, lock keys are not meaningful on Android.
+    fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        self.state(key)
+    }
+
+    /// Fails with `Unsupported`, lock keys are not meaningful on Android.
+    fn state(&self, key: LockKeys) -> LockKeyResult {
+        let result = Err(unsupported_key(key, NO_LOCK_KEYS_ON_ANDROID));
+        self.trace(format_args!("state({:?})", key), result)
+    }
+}
@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::io;
+use std::time::{Duration, Instant};
+
+use crate::{
+    LockKey, LockKeyResult, LockKeyState, LockKeyStates, LockKeyWrapper, LockKeys, LOCK_KEYS,
+};
+
+/// A lock key object caching the lock key states it reads, each key with its own maximum age, e.g. to poll a key
+/// that changes often without reading the others as often.
+///
+/// `state_cached` returns the cached state while it is younger than the maximum age of the key, reading it from the
+/// OS otherwise. The states set or toggled through the cache are cached as well, but changes made by others (e.g.
+/// the user pressing the key) are only seen once the cached state expires.
+#[derive(Debug)]
+pub struct LockKeyCache<T = LockKey> {
+    lock_key: T,
+    max_ages: [Duration; LOCK_KEYS.len()],
+    states: RefCell<[Option<(LockKeyState, Instant)>; LOCK_KEYS.len()]>,
+}
+
+impl<T: LockKeyWrapper> LockKeyCache<T> {
+    /// Wraps the lock key object, caching the states of every lock key for up to `max_age`.
+    pub fn new(lock_key: T, max_age: Duration) -> Self {
+        LockKeyCache {
+            lock_key,
+            max_ages: [max_age; LOCK_KEYS.len()],
+            states: RefCell::new([None; LOCK_KEYS.len()]),
+        }
+    }
+
+    /// Sets how long the state of the lock key is cached, overriding the maximum age given to `new`.
+    pub fn max_age(mut self, key: LockKeys, max_age: Duration) -> Self {
+        self.max_ages[key as usize] = max_age;
+        self
+    }
+
+    /// Retrieves the wrapped lock key object.
+    pub fn get_ref(&self) -> &T {
+        &self.lock_key
+    }
+
+    /// Retrieves the cached lock key state while it is younger than the maximum age of the key, reading it otherwise.
+    pub fn state_cached(&self, key: LockKeys) -> LockKeyResult {
+        if let Some((state, read_at)) = self.states.borrow()[key as usize] {
+            if read_at.elapsed() < self.max_ages[key as usize] {
+                return Ok(state);
+            }
+        }
+        self.state(key)
+    }
+
+    /// Drops the cached state of the lock key, so the next `state_cached` reads it.
+    pub fn invalidate(&self, key: LockKeys) {
+        self.states.borrow_mut()[key as usize] = None;
+    }
+
+    /// Caches the lock key state as known now, or drops it when `None`, e.g. after a failed change.
+    fn store(&self, key: LockKeys, state: Option<LockKeyState>) {
+        self.states.borrow_mut()[key as usize] = state.map(|state| (state, Instant::now()));
+    }
+}
+
+impl<T: LockKeyWrapper> LockKeyWrapper for LockKeyCache<T> {
+    /// Creates a new lock key object caching nothing, wrapping `T::new()`.
+    fn new() -> Self {
+        Self::new(T::new(), Duration::ZERO)
+    }
+
+    /// Tries to create a new lock key object caching nothing, wrapping `T::try_new()`.
+    fn try_new() -> io::Result<Self> {
+        Ok(Self::new(T::try_new()?, Duration::ZERO))
+    }
+
+    /// Sets a new state for the lock key, caching it.
+    fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        let result = self.lock_key.set(key, state);
+        self.store(key, result.as_ref().ok().map(|_| state));
+        result
+    }
+
+    /// Enables the lock key, caching its state.
+    fn enable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Enabled)
+    }
+
+    /// Disables the lock key, caching its state.
+    fn disable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Disabled)
+    }
+
+    /// Toggles the lock key state returning its previous state, caching the new one.
+    fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        let result = self.lock_key.toggle(key);
+        self.store(key, result.as_ref().ok().map(|state| state.toggle()));
+        result
+    }
+
+    /// Retrieves the lock key state, always reading it and caching it.
+    fn state(&self, key: LockKeys) -> LockKeyResult {
+        let result = self.lock_key.state(key);
+        self.store(key, result.as_ref().ok().copied());
+        result
+    }
+
+    /// Retrieves the states of the standard lock keys, always reading them and caching them.
+    fn states(&self) -> io::Result<LockKeyStates> {
+        let states = self.lock_key.states()?;
+        for (key, state) in states.iter() {
+            self.store(key, Some(state));
+        }
+        Ok(states)
+    }
+
+    /// Sets a new state for the lock key trusting `current` as its state, caching the new one.
+    fn set_known(
+        &self,
+        key: LockKeys,
+        current: LockKeyState,
+        desired: LockKeyState,
+    ) -> LockKeyResult {
+        let result = self.lock_key.set_known(key, current, desired);
+        self.store(key, result.as_ref().ok().map(|_| desired));
+        result
+    }
+}
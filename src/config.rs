@@ -0,0 +1,106 @@
+use std::fmt;
+
+use crate::LockKey;
+
+/// The effective configuration of a lock key object, e.g. the one retrieved by `LockKey::config_snapshot()`, to
+/// paste into bug reports alongside the `probe()` report.
+///
+/// Its `Display` renders one `name: value` line per setting:
+///
+/// ```text
+/// backend: Xlib
+/// display: :0
+/// install_x_error_handler: true
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigSnapshot {
+    entries: Vec<(&'static str, String)>,
+}
+
+impl ConfigSnapshot {
+    /// Iterates over the settings and their values, in the order they are displayed.
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, &str)> + '_ {
+        self.entries
+            .iter()
+            .map(|(name, value)| (*name, value.as_str()))
+    }
+
+    /// Retrieves the value of the setting, e.g. `get("backend")`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.iter()
+            .find(|&(entry, _)| entry == name)
+            .map(|(_, value)| value)
+    }
+}
+
+impl fmt::Display for ConfigSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in self.iter() {
+            writeln!(f, "{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// Serializes the snapshot as a map of the settings to their values.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ConfigSnapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+impl LockKey {
+    /// Retrieves the effective configuration of the lock key object: its backend, the OS resource it was opened on
+    /// and the builder options it was built with.
+    ///
+    /// Unlike the states, it tells how the lock key object was configured, so an issue can be reproduced.
+    pub fn config_snapshot(&self) -> ConfigSnapshot {
+        let options = &self.options;
+        let mut entries = vec![("backend", backend().to_string())];
+        #[cfg(target_os = "linux")]
+        {
+            entries.push(("display", self.display_name().unwrap_or_default()));
+            entries.push((
+                "install_x_error_handler",
+                options.install_x_error_handler.to_string(),
+            ));
+            entries.push((
+                "xauthority",
+                options
+                    .xauthority
+                    .as_ref()
+                    .map_or(String::new(), |path| path.display().to_string()),
+            ));
+            entries.push(("verify_source", format!("{:?}", options.verify_source)));
+        }
+        #[cfg(target_os = "windows")]
+        {
+            entries.push(("key_event_delay", format!("{:?}", options.key_event_delay)));
+            entries.push((
+                "scroll_lock_mode",
+                format!("{:?}", options.scroll_lock_mode),
+            ));
+            entries.push((
+                "input_idle_window",
+                format!("{:?}", options.input_idle_window),
+            ));
+        }
+        entries.push(("direct_state_only", options.direct_state_only.to_string()));
+        entries.push(("trace", self.trace.is_some().to_string()));
+        ConfigSnapshot { entries }
+    }
+}
+
+/// Names the backend of the current platform.
+fn backend() -> &'static str {
+    if cfg!(target_os = "linux") {
+        "Xlib"
+    } else if cfg!(target_os = "windows") {
+        "winuser API"
+    } else if cfg!(target_os = "macos") {
+        "IOKit"
+    } else {
+        "none"
+    }
+}
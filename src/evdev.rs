@@ -0,0 +1,319 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::mem;
+use std::os::raw::{c_int, c_long, c_ulong};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+
+use crate::linux::{poll, pollfd, POLLIN};
+use crate::watch::{self, ChangeNotifier, LastChanges, WatchHandle, WatchMechanism, WatchOptions};
+use crate::{
+    unsupported_key, LockKey, LockKeyResult, LockKeyState, LockKeyStates, LockKeys, MergePolicy,
+};
+
+#[doc(hidden)]
+pub const EV_LED: u16 = 0x11;
+#[doc(hidden)]
+pub const LED_NUML: u16 = 0x00;
+#[doc(hidden)]
+pub const LED_CAPSL: u16 = 0x01;
+#[doc(hidden)]
+pub const LED_SCROLLL: u16 = 0x02;
+
+/// The `EVIOCGLED(2)` ioctl request, reading the LED bitmask of an event device.
+const EVIOCGLED: c_ulong = (2 << 30) | (2 << 16) | ((b'E' as c_ulong) << 8) | 0x19;
+
+/// How long the event device watcher waits for events before checking whether it was stopped, in milliseconds.
+const WATCH_STOP_CHECK_TIMEOUT: c_int = 50;
+
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct input_event {
+    pub time: [c_long; 2],
+    pub type_: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// Maps a LED code to its lock key.
+fn led_key(code: u16) -> Option<LockKeys> {
+    match code {
+        LED_CAPSL => Some(LockKeys::CapitalLock),
+        LED_NUML => Some(LockKeys::NumberLock),
+        LED_SCROLLL => Some(LockKeys::ScrollingLock),
+        _ => None,
+    }
+}
+
+/// Maps a lock key to its LED code.
+fn led_code(key: LockKeys) -> Option<u16> {
+    [LED_CAPSL, LED_NUML, LED_SCROLLL]
+        .into_iter()
+        .find(|&code| led_key(code) == Some(key))
+}
+
+/// Maps a lock key to the function of its LED class device, the suffix of its name (e.g. `input3::capslock`).
+fn led_function(key: LockKeys) -> Option<&'static str> {
+    match key {
+        LockKeys::CapitalLock => Some("capslock"),
+        LockKeys::NumberLock => Some("numlock"),
+        LockKeys::ScrollingLock => Some("scrolllock"),
+        LockKeys::ShiftLock | LockKeys::Insert => None,
+    }
+}
+
+/// Lists the brightness files of the lock key LEDs under the sysfs mounted at `sys`, see `LockKey::sysfs_led_paths`.
+pub(crate) fn sysfs_led_paths_in(sys: &Path, key: LockKeys) -> Vec<PathBuf> {
+    let Some(function) = led_function(key) else {
+        return Vec::new();
+    };
+    let suffix = format!("::{}", function);
+    let mut paths: Vec<_> = fs::read_dir(sys.join("class/leds"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(&suffix))
+        .map(|entry| entry.path().join("brightness"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// Reads the state shown by every sysfs LED of the lock key under the sysfs mounted at `sys`, a LED being enabled
+/// when its brightness is not zero.
+///
+/// Fails with `NotFound` when the lock key has no LED.
+pub(crate) fn sysfs_led_states_in(sys: &Path, key: LockKeys) -> io::Result<Vec<LockKeyState>> {
+    let paths = sysfs_led_paths_in(sys, key);
+    if paths.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no sysfs LED for {:?}", key),
+        ));
+    }
+    paths
+        .iter()
+        .map(|path| {
+            let brightness = fs::read_to_string(path)?;
+            Ok((brightness.trim() != "0").into())
+        })
+        .collect()
+}
+
+/// Extracts the lock key LED changes from the `input_event` structs read from an event device.
+pub(crate) fn led_events(buffer: &[u8]) -> impl Iterator<Item = (LockKeys, LockKeyState)> + '_ {
+    buffer
+        .chunks_exact(mem::size_of::<input_event>())
+        .filter_map(|chunk| {
+            let event = unsafe { ptr::read_unaligned(chunk.as_ptr() as *const input_event) };
+            if event.type_ != EV_LED {
+                return None;
+            }
+            led_key(event.code).map(|key| (key, (event.value != 0).into()))
+        })
+}
+
+fn led_states(device: &File) -> io::Result<LockKeyStates> {
+    let mut leds = [0u8; 2];
+    if unsafe { ioctl(device.as_raw_fd(), EVIOCGLED, leds.as_mut_ptr()) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok([LED_CAPSL, LED_NUML, LED_SCROLLL]
+        .into_iter()
+        .filter_map(|code| {
+            let enabled = leds[code as usize / 8] & (1 << (code % 8)) != 0;
+            led_key(code).map(|key| (key, enabled.into()))
+        })
+        .collect())
+}
+
+/// Folds the lock key state read from each device according to the policy, skipping the devices that cannot be read.
+///
+/// Fails with the first read error when no device can be read, or with `NotFound` when there are none.
+pub(crate) fn aggregate_states<I>(devices: I, key: LockKeys, policy: MergePolicy) -> LockKeyResult
+where
+    I: IntoIterator<Item = io::Result<LockKeyStates>>,
+{
+    let mut state = None;
+    let mut error = None;
+    for device in devices {
+        match device {
+            Ok(states) => {
+                let Some(device_state) = states.get(key) else {
+                    continue;
+                };
+                state = Some(match state {
+                    Some(state) => device_state.merge(state, policy),
+                    None => device_state,
+                });
+            }
+            Err(err) => {
+                error.get_or_insert(err);
+            }
+        }
+    }
+    state.ok_or_else(|| {
+        error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no keyboard event device"))
+    })
+}
+
+impl LockKey {
+    /// Retrieves the lock key state from the LEDs of every keyboard event device, folding them according to the
+    /// policy, e.g. whether Capital Lock is on for any keyboard of a multi-keyboard system.
+    ///
+    /// As the other event device functions, it needs no X connection. The devices that cannot be read are skipped, see
+    /// `evdev_led_states` for the permissions.
+    pub fn aggregated_state(key: LockKeys, policy: MergePolicy) -> LockKeyResult {
+        if led_code(key).is_none() {
+            return Err(unsupported_key(key, "no evdev LED"));
+        }
+        let devices = Self::evdev_keyboards()?;
+        aggregate_states(
+            devices.iter().map(|device| Self::evdev_led_states(device)),
+            key,
+            policy,
+        )
+    }
+
+    /// Lists the candidate sysfs LED brightness files of the lock key, one per keyboard, e.g.
+    /// `/sys/class/leds/input3::capslock/brightness`.
+    ///
+    /// Scans `/sys/class/leds` for the LED class devices whose name ends with the LED function of the key
+    /// (`::capslock`, `::numlock` or `::scrolllock`), sorted by path. It is empty for the keys without a LED, or when
+    /// the directory cannot be read.
+    pub fn sysfs_led_paths(key: LockKeys) -> Vec<PathBuf> {
+        sysfs_led_paths_in(Path::new("/sys"), key)
+    }
+
+    /// Lists the event devices (`/dev/input/event*`) with LEDs, i.e. the keyboards, using the kernel sysfs.
+    pub fn evdev_keyboards() -> io::Result<Vec<PathBuf>> {
+        let mut devices = Vec::new();
+        for entry in fs::read_dir("/sys/class/input")? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if !name.starts_with("event") {
+                continue;
+            }
+            let leds = fs::read_to_string(entry.path().join("device/capabilities/led"))
+                .unwrap_or_default();
+            if leds.trim().trim_start_matches('0').is_empty() {
+                continue;
+            }
+            devices.push(Path::new("/dev/input").join(&*name));
+        }
+        devices.sort();
+        Ok(devices)
+    }
+
+    /// Retrieves the standard lock key states from the LEDs of a keyboard event device (e.g. `/dev/input/event3`),
+    /// without X.
+    ///
+    /// Requires read access to the device, usually root or the `input` group.
+    pub fn evdev_led_states(device: &Path) -> io::Result<LockKeyStates> {
+        led_states(&File::open(device)?)
+    }
+
+    /// Watches the standard lock keys from the `EV_LED` events of a keyboard event device, calling `f` with each
+    /// changed key and its new state, as `watch` but without X.
+    ///
+    /// The watcher stops once the device cannot be read anymore, e.g. unplugged, its error being kept for
+    /// `WatchHandle::take_error`.
+    ///
+    /// Requires read access to the device, usually root or the `input` group.
+    pub fn watch_evdev_leds<F>(
+        device: &Path,
+        options: WatchOptions,
+        f: F,
+    ) -> io::Result<WatchHandle>
+    where
+        F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+    {
+        let device = File::open(device)?;
+        watch::spawn_watcher(move |stop, ready, last_changes| {
+            watch_device_leds(device, options, f, stop, ready, last_changes)
+        })
+    }
+}
+
+/// Opens the first keyboard event device that can be read, e.g. for watching the lock keys without X.
+pub(crate) fn open_readable_keyboard() -> Option<File> {
+    LockKey::evdev_keyboards()
+        .ok()?
+        .iter()
+        .find_map(|device| File::open(device).ok())
+}
+
+/// Runs an event device watcher on the opened keyboard event device, reporting its mechanism once its LED states
+/// were read.
+pub(crate) fn watch_device_leds<F>(
+    mut device: File,
+    options: WatchOptions,
+    f: F,
+    stop: Receiver<()>,
+    ready: Sender<io::Result<WatchMechanism>>,
+    last_changes: LastChanges,
+) -> io::Result<()>
+where
+    F: FnMut(LockKeys, LockKeyState),
+{
+    let states = match led_states(&device) {
+        Ok(states) => states,
+        Err(err) => {
+            let _ = ready.send(Err(err));
+            return Ok(());
+        }
+    };
+    let mut notifier = ChangeNotifier::new(states, options, last_changes, f);
+    let _ = ready.send(Ok(WatchMechanism::EvdevLed));
+    read_led_events(&mut device, &mut notifier, states, &stop)
+}
+
+/// Reads the `EV_LED` events of the device until the watcher is stopped, failing once the device cannot be read
+/// anymore, e.g. with `UnexpectedEof` when it was unplugged.
+pub(crate) fn read_led_events<R, F>(
+    device: &mut R,
+    notifier: &mut ChangeNotifier<F>,
+    mut states: LockKeyStates,
+    stop: &Receiver<()>,
+) -> io::Result<()>
+where
+    R: Read + AsRawFd,
+    F: FnMut(LockKeys, LockKeyState),
+{
+    let mut fd = pollfd {
+        fd: device.as_raw_fd(),
+        events: POLLIN,
+        revents: 0,
+    };
+    let mut buffer = [0u8; 64 * mem::size_of::<input_event>()];
+    while let Err(TryRecvError::Empty) = stop.try_recv() {
+        if unsafe { poll(&mut fd, 1, WATCH_STOP_CHECK_TIMEOUT) } <= 0 {
+            notifier.flush();
+            continue;
+        }
+        let len = match device.read(&mut buffer) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "keyboard event device closed",
+                ))
+            }
+            Ok(len) => len,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        for (key, state) in led_events(&buffer[..len]) {
+            states.insert(key, state);
+        }
+        notifier.observe(states);
+    }
+    Ok(())
+}
@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LockChange, LockKeyResult, LockKeyState, LockKeyWrapper, LockKeys};
+
+/// A request sent by an `IpcClient`, one JSON object per line.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Request {
+    Set(LockChange),
+    Get(LockKeys),
+    Toggle(LockKeys),
+}
+
+/// The reply of an `IpcServer` to a request, one JSON object per line.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Reply {
+    Ok(LockKeyState),
+    Err(String),
+}
+
+/// A server handling the lock keys on behalf of the `IpcClient`s connected to its Unix socket, e.g. a privileged
+/// daemon owning the X or HID access while unprivileged apps request the changes.
+///
+/// The socket file is removed when the server is dropped. Restricting who can connect is left to the permissions of
+/// the socket file or of its directory.
+#[derive(Debug)]
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl IpcServer {
+    /// Binds the server to a new Unix socket at `path`, failing with `AddrInUse` when the file already exists.
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(IpcServer {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Retrieves the path of the socket.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Handles the clients with the lock key object, one connection at a time, until accepting a connection fails.
+    ///
+    /// A client disconnecting or sending an invalid request only ends its own connection.
+    pub fn serve<T: LockKeyWrapper>(&self, lock_key: &T) -> io::Result<()> {
+        loop {
+            let (stream, _) = self.listener.accept()?;
+            let _ = handle(lock_key, stream);
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Handles the requests of a connection until the client disconnects.
+///
+/// As in `LockKeyActor`, `Set` reads the state first to send nothing when it already matches.
+fn handle<T: LockKeyWrapper>(lock_key: &T, stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let request: Request = serde_json::from_str(&line?)?;
+        let result = match request {
+            Request::Set(change) => lock_key
+                .state(change.key)
+                .and_then(|current| lock_key.set_known(change.key, current, change.state)),
+            Request::Get(key) => lock_key.state(key),
+            Request::Toggle(key) => lock_key.toggle(key),
+        };
+        let reply = match result {
+            Ok(state) => Reply::Ok(state),
+            Err(err) => Reply::Err(err.to_string()),
+        };
+        writeln!(writer, "{}", serde_json::to_string(&reply)?)?;
+    }
+    Ok(())
+}
+
+/// A client handling the lock keys through an `IpcServer`.
+///
+/// The errors of the server are received as their message only, so they all have the `Other` kind.
+#[derive(Debug)]
+pub struct IpcClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl IpcClient {
+    /// Connects to the server listening on the Unix socket at `path`.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        Ok(IpcClient {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Sets a new state for the lock key through the server.
+    pub fn set(&mut self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        self.request(&Request::Set(LockChange::new(key, state)))
+    }
+
+    /// Retrieves the lock key state through the server.
+    pub fn state(&mut self, key: LockKeys) -> LockKeyResult {
+        self.request(&Request::Get(key))
+    }
+
+    /// Toggles the lock key state returning its previous state through the server.
+    pub fn toggle(&mut self, key: LockKeys) -> LockKeyResult {
+        self.request(&Request::Toggle(key))
+    }
+
+    /// Sends the request waiting for its reply, failing with `BrokenPipe` when the server closed the connection.
+    fn request(&mut self, request: &Request) -> LockKeyResult {
+        writeln!(self.writer, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::BrokenPipe,
+                "lock key server closed the connection",
+            ));
+        }
+        match serde_json::from_str(&line)? {
+            Reply::Ok(state) => Ok(state),
+            Reply::Err(message) => Err(io::Error::other(message)),
+        }
+    }
+}
@@ -0,0 +1,29 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::{LockKeyState, LockKeys};
+
+/// A lock key change as written by `change_to_json`.
+#[derive(Serialize)]
+struct ChangeRecord {
+    key: LockKeys,
+    state: LockKeyState,
+    ts: u64,
+}
+
+/// Formats a lock key change as a JSON object, e.g. `{"key":"capital_lock","state":"enabled","ts":1700000000000}`,
+/// where `ts` is the current time in milliseconds since the Unix epoch.
+///
+/// Calling it from a watcher callback produces a stream of JSON lines for tools such as `jq`.
+pub fn change_to_json(key: LockKeys, state: LockKeyState) -> String {
+    let ts = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    change_to_json_at(key, state, ts)
+}
+
+pub(crate) fn change_to_json_at(key: LockKeys, state: LockKeyState, ts: u64) -> String {
+    // Serializing plain enums and integers cannot fail.
+    serde_json::to_string(&ChangeRecord { key, state, ts }).unwrap_or_default()
+}
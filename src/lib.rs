@@ -18,32 +18,160 @@
 #[cfg(target_os = "linux")]
 mod linux;
 
+#[cfg(target_os = "linux")]
+mod evdev;
+
 #[cfg(target_os = "windows")]
 mod windows;
 
 #[cfg(target_os = "macos")]
 mod macos;
 
+#[cfg(target_os = "android")]
+mod android;
+
+mod actor;
+mod cache;
+mod config;
+#[cfg(all(feature = "ipc", unix))]
+mod ipc;
+#[cfg(feature = "serde")]
+mod json;
+mod probe;
+#[cfg(feature = "serde")]
+mod profile;
+mod timer;
+mod undo;
+mod watch;
+
+pub use actor::{LockKeyActor, LockKeyClient};
+pub use cache::LockKeyCache;
+pub use config::ConfigSnapshot;
+#[cfg(all(feature = "ipc", unix))]
+pub use ipc::{IpcClient, IpcServer};
+#[cfg(feature = "serde")]
+pub use json::change_to_json;
+#[cfg(target_os = "linux")]
+pub use linux::{LockBehavior, VerifySource};
+pub use probe::{ProbeReport, ProbeStream};
+#[cfg(feature = "serde")]
+pub use profile::LockKeyProfile;
+pub use timer::TimerHandle;
+pub use undo::LockKeyUndo;
+pub use watch::{WatchHandle, WatchMechanism, WatchOptions};
+#[cfg(target_os = "windows")]
+pub use windows::ScrollLockMode;
+
+use std::array;
+use std::error;
 use std::fmt;
 use std::io;
+use std::ops;
+use std::panic;
+#[cfg(feature = "serde")]
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::str;
+use std::time::{Duration, Instant};
 
 #[doc(hidden)]
 enum LockKeyHandle {}
 
 // Indicates the lock key state, i.e. enabled/disabled.
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum LockKeyState {
     Enabled,
     Disabled,
 }
 
 impl LockKeyState {
+    /// Returns both lock key states, i.e. Enabled and Disabled.
+    ///
+    /// Pairs with `LockKeys::all()` to go through every key and state combination:
+    ///
+    /// ```rust
+    /// use lock_keys::*;
+    ///
+    /// let mut combinations = Vec::new();
+    /// for key in LockKeys::all() {
+    ///     for state in LockKeyState::all() {
+    ///         combinations.push((key, state));
+    ///     }
+    /// }
+    /// assert_eq!(combinations.len(), 6);
+    /// ```
+    pub fn all() -> [LockKeyState; 2] {
+        [LockKeyState::Enabled, LockKeyState::Disabled]
+    }
+
+    /// Returns the opposite state, same as `toggled`. Unlike `LockKeyWrapper::toggle` it does not change any key.
     pub fn toggle(self) -> Self {
         match self {
             Self::Enabled => Self::Disabled,
             Self::Disabled => Self::Enabled,
         }
     }
+
+    /// Returns the opposite state without changing any key, unlike `LockKeyWrapper::toggle` which does the I/O.
+    #[doc(alias = "toggle")]
+    pub fn toggled(self) -> Self {
+        self.toggle()
+    }
+
+    /// Describes the state of the lock key in a user-facing sentence, e.g. `Caps Lock is currently enabled.` for
+    /// tooltips.
+    pub fn describe(self, key: LockKeys) -> String {
+        format!("{} is currently {}.", key, self)
+    }
+
+    /// Folds the state with another state of the same lock key according to the policy.
+    pub fn merge(self, other: LockKeyState, policy: MergePolicy) -> LockKeyState {
+        match policy {
+            MergePolicy::Any => (bool::from(self) || bool::from(other)).into(),
+            MergePolicy::All => (bool::from(self) && bool::from(other)).into(),
+        }
+    }
+
+    /// Compares the state against the desired one, telling whether setting it would change anything.
+    pub fn compare(&self, desired: LockKeyState) -> Change {
+        if *self == desired {
+            Change::NoChange
+        } else {
+            Change::WouldChange {
+                from: *self,
+                to: desired,
+            }
+        }
+    }
+
+    /// Leniently parses a lock key state from user input, ignoring case and surrounding whitespace.
+    ///
+    /// Accepts `enabled`/`disabled`, `enable`/`disable`, `on`/`off`, `true`/`false`, `t`/`f`, `yes`/`no`,
+    /// `y`/`n` and `1`/`0`, returning `None` for anything else.
+    pub fn parse_bool_like(s: &str) -> Option<LockKeyState> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "enabled" | "enable" | "on" | "true" | "t" | "yes" | "y" | "1" => {
+                Some(LockKeyState::Enabled)
+            }
+            "disabled" | "disable" | "off" | "false" | "f" | "no" | "n" | "0" => {
+                Some(LockKeyState::Disabled)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl ops::Not for LockKeyState {
+    type Output = Self;
+
+    /// Returns the opposite state, same as `toggled`.
+    fn not(self) -> Self::Output {
+        self.toggled()
+    }
 }
 
 impl From<bool> for LockKeyState {
@@ -71,20 +199,746 @@ impl fmt::Display for LockKeyState {
     }
 }
 
+impl From<LockKeyState> for String {
+    fn from(val: LockKeyState) -> Self {
+        val.to_string()
+    }
+}
+
+impl str::FromStr for LockKeyState {
+    type Err = ParseStateError;
+
+    /// Parses the canonical lock key state names, i.e. `enabled` and `disabled`, see `parse_bool_like` for user input.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enabled" => Ok(LockKeyState::Enabled),
+            "disabled" => Ok(LockKeyState::Disabled),
+            _ => Err(ParseStateError {
+                input: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&str> for LockKeyState {
+    type Error = ParseStateError;
+
+    fn try_from(val: &str) -> Result<Self, Self::Error> {
+        val.parse()
+    }
+}
+
+/// The error returned when a lock key state cannot be parsed, carrying the invalid input.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseStateError {
+    input: String,
+}
+
+impl ParseStateError {
+    /// Retrieves the input that could not be parsed.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+}
+
+impl fmt::Display for ParseStateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid lock key state {:?}", self.input)
+    }
+}
+
+impl error::Error for ParseStateError {}
+
+/// How `toggle_with_policy` handles a lock key state that cannot be read.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum TogglePolicy {
+    /// Fails with the read error without toggling, as `toggle` does.
+    #[default]
+    StrictRead,
+    /// Falls back to a blind toggle, i.e. sending the key press without knowing the state, when the backend can.
+    BlindOnReadFailure,
+}
+
+/// How `LockKeyState::merge` folds the states of the same lock key, e.g. read from several keyboards.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MergePolicy {
+    /// Enabled when any of the states is enabled.
+    Any,
+    /// Enabled only when all of the states are enabled.
+    All,
+}
+
+/// The result of comparing a lock key state against the desired one, see `LockKeyState::compare`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Change {
+    /// The state is already the desired one.
+    NoChange,
+    /// Setting the desired state would change it.
+    WouldChange {
+        from: LockKeyState,
+        to: LockKeyState,
+    },
+}
+
+/// A lock key state as reported to a UI, telling a confidently read state from an indeterminate one, see
+/// `LockKeyWrapper::report`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LockKeyReport {
+    Enabled,
+    Disabled,
+    /// The state cannot be told, e.g. the lock key is unsupported or its state cannot be read.
+    Unknown,
+}
+
+impl LockKeyReport {
+    /// Retrieves the reported state, or `None` when it is unknown.
+    pub fn state(self) -> Option<LockKeyState> {
+        match self {
+            LockKeyReport::Enabled => Some(LockKeyState::Enabled),
+            LockKeyReport::Disabled => Some(LockKeyState::Disabled),
+            LockKeyReport::Unknown => None,
+        }
+    }
+}
+
+impl From<LockKeyState> for LockKeyReport {
+    fn from(val: LockKeyState) -> Self {
+        match val {
+            LockKeyState::Enabled => LockKeyReport::Enabled,
+            LockKeyState::Disabled => LockKeyReport::Disabled,
+        }
+    }
+}
+
+impl From<Option<LockKeyState>> for LockKeyReport {
+    fn from(val: Option<LockKeyState>) -> Self {
+        val.map_or(LockKeyReport::Unknown, Into::into)
+    }
+}
+
+/// A lock key state change, e.g. for `LockKeyWrapper::apply_transactional`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct LockChange {
+    pub key: LockKeys,
+    pub state: LockKeyState,
+}
+
+impl LockChange {
+    /// Creates a change setting the lock key to the state.
+    pub fn new(key: LockKeys, state: LockKeyState) -> Self {
+        LockChange { key, state }
+    }
+}
+
 /// A specialized `Result` type lock key handling.
 pub type LockKeyResult = io::Result<LockKeyState>;
 
-/// The available lock keys for handling, i.e. Capital Lock, Number Lock and Scrolling Lock.
+/// The available lock keys for handling, i.e. Capital Lock, Number Lock, Scrolling Lock, Shift Lock and Insert.
 #[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum LockKeys {
     CapitalLock,
     NumberLock,
     ScrollingLock,
+    /// Shift Lock, available on Linux only when the keyboard layout provides it.
+    ShiftLock,
+    /// Insert (overwrite mode), opt-in as it is a soft toggle kept by the OS without a keyboard LED. Available on
+    /// Windows, and on Linux only when the keyboard layout maps it to a lock modifier.
+    Insert,
+}
+
+/// Renders the user-facing name of the lock key, e.g. `Caps Lock`.
+impl fmt::Display for LockKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match *self {
+            LockKeys::CapitalLock => "Caps Lock",
+            LockKeys::NumberLock => "Num Lock",
+            LockKeys::ScrollingLock => "Scroll Lock",
+            LockKeys::ShiftLock => "Shift Lock",
+            LockKeys::Insert => "Insert",
+        })
+    }
+}
+
+/// Every lock key variant, indexed by its discriminant.
+const LOCK_KEYS: [LockKeys; 5] = [
+    LockKeys::CapitalLock,
+    LockKeys::NumberLock,
+    LockKeys::ScrollingLock,
+    LockKeys::ShiftLock,
+    LockKeys::Insert,
+];
+
+impl LockKeys {
+    /// Returns the standard lock keys, i.e. Capital Lock, Number Lock and Scrolling Lock.
+    pub fn all() -> [LockKeys; 3] {
+        [
+            LockKeys::CapitalLock,
+            LockKeys::NumberLock,
+            LockKeys::ScrollingLock,
+        ]
+    }
+
+    /// Returns the lock keys with a keyboard LED, i.e. Capital Lock, Number Lock and Scrolling Lock, e.g. to reset the
+    /// LEDs without touching the modifier-only locks.
+    pub fn indicators() -> &'static [LockKeys] {
+        &[
+            LockKeys::CapitalLock,
+            LockKeys::NumberLock,
+            LockKeys::ScrollingLock,
+        ]
+    }
+
+    /// Returns the lock keys acting as sticky modifiers without a keyboard LED, i.e. Shift Lock.
+    ///
+    /// Insert is in neither group, as it is a soft toggle kept by the OS.
+    pub fn modifiers() -> &'static [LockKeys] {
+        &[LockKeys::ShiftLock]
+    }
+
+    /// Checks whether the lock key is handled on the current platform, e.g. Scrolling Lock is not on macOS.
+    pub fn is_supported(self) -> bool {
+        // Android has no lock key handling API, see `NO_LOCK_KEYS_ON_ANDROID`.
+        if cfg!(target_os = "android") {
+            return false;
+        }
+        match self {
+            LockKeys::CapitalLock | LockKeys::NumberLock => true,
+            LockKeys::ScrollingLock => !cfg!(target_os = "macos"),
+            LockKeys::ShiftLock => cfg!(target_os = "linux"),
+            LockKeys::Insert => !cfg!(target_os = "macos"),
+        }
+    }
+
+    /// Retrieves the name of the OS constant identifying the lock key on the current platform, e.g. `XK_Caps_Lock`,
+    /// `VK_CAPITAL` or `kIOHIDCapsLockState`, for unambiguous diagnostics.
+    ///
+    /// It is empty for the lock keys without a constant on the current platform, e.g. Scrolling Lock on macOS.
+    pub fn platform_constant_name(self) -> &'static str {
+        #[cfg(target_os = "linux")]
+        return match self {
+            LockKeys::CapitalLock => "XK_Caps_Lock",
+            LockKeys::NumberLock => "XK_Num_Lock",
+            LockKeys::ScrollingLock => "XK_Scroll_Lock",
+            LockKeys::ShiftLock => "XK_Shift_Lock",
+            LockKeys::Insert => "XK_Insert",
+        };
+        #[cfg(target_os = "windows")]
+        return match self {
+            LockKeys::CapitalLock => "VK_CAPITAL",
+            LockKeys::NumberLock => "VK_NUMLOCK",
+            LockKeys::ScrollingLock => "VK_SCROLL",
+            LockKeys::Insert => "VK_INSERT",
+            LockKeys::ShiftLock => "",
+        };
+        #[cfg(target_os = "macos")]
+        return match self {
+            LockKeys::CapitalLock => "kIOHIDCapsLockState",
+            LockKeys::NumberLock => "kIOHIDNumLockState",
+            LockKeys::ScrollingLock | LockKeys::ShiftLock | LockKeys::Insert => "",
+        };
+        #[cfg(target_os = "android")]
+        return "";
+    }
+
+    /// Retrieves the conventional boot state of the lock key on the current platform, i.e. Number Lock enabled on
+    /// Windows and every other lock key disabled.
+    ///
+    /// These are conventions, not guarantees: the firmware, the login manager or the user configuration may boot with
+    /// other states.
+    pub fn platform_default(self) -> LockKeyState {
+        match self {
+            LockKeys::NumberLock => cfg!(target_os = "windows").into(),
+            LockKeys::CapitalLock
+            | LockKeys::ScrollingLock
+            | LockKeys::ShiftLock
+            | LockKeys::Insert => LockKeyState::Disabled,
+        }
+    }
+
+    /// Iterates over the standard lock keys, in the same order as `all()`.
+    pub fn iter() -> LockKeysIter {
+        LockKeysIter {
+            keys: Self::all().into_iter(),
+        }
+    }
+}
+
+/// An iterator over the standard lock keys, created by `LockKeys::iter()`.
+#[derive(Clone, Debug)]
+pub struct LockKeysIter {
+    keys: array::IntoIter<LockKeys, 3>,
+}
+
+impl Iterator for LockKeysIter {
+    type Item = LockKeys;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.keys.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.keys.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for LockKeysIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.keys.next_back()
+    }
+}
+
+impl ExactSizeIterator for LockKeysIter {}
+
+/// A set of lock keys.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct LockKeySet {
+    bits: u8,
+}
+
+impl LockKeySet {
+    /// Creates an empty set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks whether the set has no lock keys.
+    pub fn is_empty(&self) -> bool {
+        self.bits == 0
+    }
+
+    /// Returns the number of lock keys in the set.
+    pub fn len(&self) -> usize {
+        self.bits.count_ones() as usize
+    }
+
+    /// Checks whether the lock key is in the set.
+    pub fn contains(&self, key: LockKeys) -> bool {
+        self.bits & Self::bit(key) != 0
+    }
+
+    /// Adds the lock key to the set.
+    pub fn insert(&mut self, key: LockKeys) {
+        self.bits |= Self::bit(key);
+    }
+
+    /// Removes the lock key from the set.
+    pub fn remove(&mut self, key: LockKeys) {
+        self.bits &= !Self::bit(key);
+    }
+
+    /// Returns the lock keys supported on the current platform that are not in the set.
+    pub fn complement(&self) -> Self {
+        LOCK_KEYS
+            .into_iter()
+            .filter(|&key| key.is_supported() && !self.contains(key))
+            .collect()
+    }
+
+    /// Iterates over the lock keys in the set.
+    pub fn iter(&self) -> impl Iterator<Item = LockKeys> + '_ {
+        LOCK_KEYS.into_iter().filter(|&key| self.contains(key))
+    }
+
+    fn bit(key: LockKeys) -> u8 {
+        1 << key as u8
+    }
+}
+
+impl From<LockKeys> for LockKeySet {
+    fn from(key: LockKeys) -> Self {
+        LockKeySet {
+            bits: Self::bit(key),
+        }
+    }
+}
+
+impl FromIterator<LockKeys> for LockKeySet {
+    fn from_iter<I: IntoIterator<Item = LockKeys>>(iter: I) -> Self {
+        let mut set = LockKeySet::new();
+        for key in iter {
+            set.insert(key);
+        }
+        set
+    }
+}
+
+impl ops::BitOr for LockKeySet {
+    type Output = Self;
+
+    /// Returns the union of the sets.
+    fn bitor(self, rhs: Self) -> Self::Output {
+        LockKeySet {
+            bits: self.bits | rhs.bits,
+        }
+    }
+}
+
+impl ops::BitAnd for LockKeySet {
+    type Output = Self;
+
+    /// Returns the intersection of the sets.
+    fn bitand(self, rhs: Self) -> Self::Output {
+        LockKeySet {
+            bits: self.bits & rhs.bits,
+        }
+    }
+}
+
+impl ops::Sub for LockKeySet {
+    type Output = Self;
+
+    /// Returns the difference of the sets.
+    fn sub(self, rhs: Self) -> Self::Output {
+        LockKeySet {
+            bits: self.bits & !rhs.bits,
+        }
+    }
+}
+
+/// A snapshot of lock key states, e.g. the one retrieved by `LockKeyWrapper::states()`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct LockKeyStates {
+    states: [Option<LockKeyState>; LOCK_KEYS.len()],
+}
+
+impl LockKeyStates {
+    /// Creates an empty snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Retrieves the lock key state, or `None` when it is not in the snapshot.
+    pub fn get(&self, key: LockKeys) -> Option<LockKeyState> {
+        self.states[key as usize]
+    }
+
+    /// Stores the lock key state in the snapshot.
+    pub fn insert(&mut self, key: LockKeys, state: LockKeyState) {
+        self.states[key as usize] = Some(state);
+    }
+
+    /// Iterates over the lock keys in the snapshot and their states.
+    pub fn iter(&self) -> impl Iterator<Item = (LockKeys, LockKeyState)> + '_ {
+        LOCK_KEYS
+            .into_iter()
+            .filter_map(|key| self.get(key).map(|state| (key, state)))
+    }
+
+    /// Converts the states of the standard lock keys to booleans ordered as `LockKeys::all()`, e.g. for a C struct,
+    /// a key missing from the snapshot being `false`.
+    pub fn to_bools(&self) -> [bool; 3] {
+        LockKeys::all().map(|key| self.get(key).is_some_and(bool::from))
+    }
+
+    /// Creates a snapshot of the standard lock keys from booleans ordered as `LockKeys::all()`, the inverse of
+    /// `to_bools`.
+    pub fn from_bools(bools: [bool; 3]) -> LockKeyStates {
+        LockKeys::all()
+            .into_iter()
+            .zip(bools)
+            .map(|(key, state)| (key, state.into()))
+            .collect()
+    }
+
+    /// Returns the snapshot with the opposite state for every lock key in it.
+    pub fn toggled(&self) -> LockKeyStates {
+        self.iter()
+            .map(|(key, state)| (key, state.toggle()))
+            .collect()
+    }
+
+    /// Lists the changes needed to go from this snapshot to the `target` one, i.e. for the lock keys present in both
+    /// with different states, e.g. for `LockKeyWrapper::apply_transactional`.
+    pub fn plan_to(&self, target: &LockKeyStates) -> Vec<LockChange> {
+        self.diff(target)
+            .into_iter()
+            .map(|(key, _, state)| LockChange::new(key, state))
+            .collect()
+    }
+
+    /// Lists the lock keys present in both snapshots with different states, as `(key, self_state, other_state)`.
+    pub fn diff(&self, other: &LockKeyStates) -> Vec<(LockKeys, LockKeyState, LockKeyState)> {
+        self.iter()
+            .filter_map(|(key, state)| match other.get(key) {
+                Some(other_state) if other_state != state => Some((key, state, other_state)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl FromIterator<(LockKeys, LockKeyState)> for LockKeyStates {
+    fn from_iter<I: IntoIterator<Item = (LockKeys, LockKeyState)>>(iter: I) -> Self {
+        let mut states = LockKeyStates::new();
+        for (key, state) in iter {
+            states.insert(key, state);
+        }
+        states
+    }
+}
+
+/// The reason of the `Unsupported` errors for the lock keys missing from the current keyboard layout.
+#[cfg_attr(any(target_os = "macos", target_os = "android"), allow(dead_code))]
+const UNMAPPED_ON_LAYOUT: &str = "key unmapped on current layout";
+
+/// The detail of an `Unsupported` lock key error, i.e. which key, on which platform and why.
+///
+/// Carried as the inner error of the `io::Error`, see `UnsupportedKeyError::from_io_error`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct UnsupportedKeyError {
+    key: LockKeys,
+    platform: &'static str,
+    reason: &'static str,
+}
+
+impl UnsupportedKeyError {
+    /// Retrieves the detail of an `Unsupported` lock key error, or `None` for the other errors.
+    pub fn from_io_error(err: &io::Error) -> Option<&UnsupportedKeyError> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    /// Retrieves the unsupported lock key.
+    pub fn key(&self) -> LockKeys {
+        self.key
+    }
+
+    /// Retrieves the platform the lock key is unsupported on, as `std::env::consts::OS`, e.g. `"macos"`.
+    pub fn platform(&self) -> &'static str {
+        self.platform
+    }
+
+    /// Retrieves why the lock key is unsupported, e.g. `"not exposed by IOKit"`.
+    pub fn reason(&self) -> &'static str {
+        self.reason
+    }
+}
+
+impl fmt::Display for UnsupportedKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} is not supported on {}: {}",
+            self.key, self.platform, self.reason
+        )
+    }
+}
+
+impl error::Error for UnsupportedKeyError {}
+
+#[doc(hidden)]
+fn unsupported_key(key: LockKeys, reason: &'static str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        UnsupportedKeyError {
+            key,
+            platform: std::env::consts::OS,
+            reason,
+        },
+    )
+}
+
+/// The reason of the `Unsupported` errors on Android, which has no lock key handling API.
+const NO_LOCK_KEYS_ON_ANDROID: &str = "lock keys are not meaningful on Android";
+
+/// Fails with `Unsupported` for the lock keys not handled on the current platform, before any backend call.
+fn check_supported(key: LockKeys) -> io::Result<()> {
+    if key.is_supported() {
+        return Ok(());
+    }
+    let reason = if cfg!(target_os = "macos") {
+        "not exposed by IOKit"
+    } else if cfg!(target_os = "windows") {
+        "no virtual key on Windows"
+    } else if cfg!(target_os = "android") {
+        NO_LOCK_KEYS_ON_ANDROID
+    } else {
+        "not handled on this platform"
+    };
+    Err(unsupported_key(key, reason))
+}
+
+/// Maps a lock key handling error to a stable process exit code, allowing scripts to branch on the exit status.
+///
+/// | Error kind         | Exit code |
+/// |--------------------|-----------|
+/// | `NotFound`         | 2         |
+/// | `PermissionDenied` | 13        |
+/// | `InvalidInput`     | 22        |
+/// | `BrokenPipe`       | 32        |
+/// | `Unsupported`      | 95        |
+/// | `NotConnected`     | 107       |
+/// | `TimedOut`         | 110       |
+/// | any other          | 1         |
+pub fn error_exit_code(err: &io::Error) -> i32 {
+    match err.kind() {
+        io::ErrorKind::NotFound => 2,
+        io::ErrorKind::PermissionDenied => 13,
+        io::ErrorKind::InvalidInput => 22,
+        io::ErrorKind::BrokenPipe => 32,
+        io::ErrorKind::Unsupported => 95,
+        io::ErrorKind::NotConnected => 107,
+        io::ErrorKind::TimedOut => 110,
+        _ => 1,
+    }
+}
+
+/// Reads the lock key states of every lock key object, e.g. one per X display of a multi-seat system, in order.
+///
+/// The reads are sequential: a lock key object owns its OS handle (e.g. an X display connection, which Xlib does not
+/// share across threads) so it cannot be read from other threads. To hide the latency of many remote displays, read
+/// each one from its own `LockKeyActor`.
+pub fn read_all<T: LockKeyWrapper>(lock_keys: &[&T]) -> Vec<io::Result<LockKeyStates>> {
+    lock_keys.iter().map(|lock_key| lock_key.states()).collect()
 }
 
 /// The lock ley object to hold the OS specific handle when it is required.
 pub struct LockKey {
     handle: *mut LockKeyHandle,
+    #[cfg_attr(any(target_os = "macos", target_os = "android"), allow(dead_code))]
+    options: LockKeyBuilder,
+    trace: Option<TraceCallback>,
+    #[cfg(target_os = "linux")]
+    indicators: std::cell::OnceCell<linux::IndicatorIndices>,
+}
+
+/// Chains a panic hook calling `restore` before the previous hook.
+fn restore_on_panic(restore: impl Fn() + Send + Sync + 'static) {
+    let previous = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore();
+        previous(info);
+    }));
+}
+
+/// A callback receiving the description of a backend operation and its result.
+type TraceCallback = Box<dyn Fn(&str) + Send + Sync>;
+
+impl LockKey {
+    /// Creates a builder to customize the lock key object.
+    pub fn builder() -> LockKeyBuilder {
+        LockKeyBuilder::new()
+    }
+
+    /// Checks whether both lock key objects hold the same OS handle, e.g. to deduplicate them.
+    ///
+    /// Each one opens its own handle on Linux (an X display connection) and macOS (an IOKit connection), even for
+    /// the same display, see `same_display`. On Windows there is no handle, so it is always `true`.
+    pub fn same_handle(&self, other: &LockKey) -> bool {
+        self.handle == other.handle
+    }
+
+    /// Checks whether changing the lock key states needs permissions granted to the process on the current backend,
+    /// e.g. to warn before attempting a change.
+    ///
+    /// - Xlib (Linux): no, any client of the X display can lock the modifiers.
+    /// - winuser API (Windows): no, though the injected key events do not reach elevated windows.
+    /// - IOKit (macOS): yes, the process needs the Accessibility/Input Monitoring permission.
+    pub fn requires_privileges(&self) -> bool {
+        cfg!(target_os = "macos")
+    }
+
+    /// Installs a panic hook restoring the lock keys to the `snapshot` states, e.g. so a panic while Capital Lock is
+    /// temporarily enabled does not leave it stuck on.
+    ///
+    /// The hook is process-wide: it runs on every panic of any thread, including the caught ones, and stays installed
+    /// for the lifetime of the process. It restores the states through its own OS handle opened with the options of
+    /// this lock key object, ignoring the errors, then calls the previous hook.
+    pub fn install_restore_on_panic(&self, snapshot: LockKeyStates) {
+        let options = self.options.clone();
+        restore_on_panic(move || {
+            if let Ok(lock_key) = options.clone().build() {
+                let _ = lock_key.apply_spec(&snapshot);
+            }
+        });
+    }
+
+    /// Sets a callback invoked with the description of each backend operation and its result, e.g.
+    /// `XkbLockModifiers(CapitalLock, enabled) = Ok(Enabled)`, for diagnosing keyboard issues.
+    pub fn set_trace_callback(&mut self, f: impl Fn(&str) + Send + Sync + 'static) {
+        self.trace = Some(Box::new(f));
+    }
+
+    /// Passes the description of the backend `operation` and its `result` to the trace callback, if any.
+    pub(crate) fn trace<T: fmt::Debug>(
+        &self,
+        operation: fmt::Arguments<'_>,
+        result: io::Result<T>,
+    ) -> io::Result<T> {
+        if let Some(trace) = &self.trace {
+            trace(&format!("{} = {:?}", operation, result));
+        }
+        result
+    }
+}
+
+/// A builder for lock key objects with custom options, e.g. `LockKey::builder().build()`.
+#[derive(Clone, Debug)]
+pub struct LockKeyBuilder {
+    #[cfg(target_os = "windows")]
+    key_event_delay: Duration,
+    #[cfg(target_os = "windows")]
+    scroll_lock_mode: ScrollLockMode,
+    #[cfg(target_os = "windows")]
+    input_idle_window: Duration,
+    #[cfg(target_os = "linux")]
+    install_x_error_handler: bool,
+    #[cfg(target_os = "linux")]
+    xauthority: Option<PathBuf>,
+    #[cfg(target_os = "linux")]
+    verify_source: VerifySource,
+    #[cfg_attr(not(target_os = "windows"), allow(dead_code))]
+    direct_state_only: bool,
+}
+
+#[cfg_attr(
+    any(target_os = "macos", target_os = "android"),
+    allow(clippy::derivable_impls)
+)]
+impl Default for LockKeyBuilder {
+    fn default() -> Self {
+        LockKeyBuilder {
+            #[cfg(target_os = "windows")]
+            key_event_delay: Duration::ZERO,
+            #[cfg(target_os = "windows")]
+            scroll_lock_mode: ScrollLockMode::Inject,
+            #[cfg(target_os = "windows")]
+            input_idle_window: Duration::ZERO,
+            #[cfg(target_os = "linux")]
+            install_x_error_handler: true,
+            #[cfg(target_os = "linux")]
+            xauthority: None,
+            #[cfg(target_os = "linux")]
+            verify_source: VerifySource::Logical,
+            direct_state_only: false,
+        }
+    }
+}
+
+impl LockKeyBuilder {
+    /// Creates a builder with the default options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to change the lock key states directly only, never injecting key events, disabled by default.
+    ///
+    /// Linux (`XkbLockModifiers`) and macOS (IOKit) always change the states directly. On Windows `set` changes the
+    /// key state table of the calling thread with `SetKeyboardState` instead of sending key presses, so no keystroke
+    /// can be observed (e.g. by games or remote sessions), but neither the other threads nor the keyboard LED see
+    /// the change. The operations that only work by injecting key events (e.g. `hold`) fail with `Unsupported`.
+    pub fn direct_state_only(mut self, direct: bool) -> Self {
+        self.direct_state_only = direct;
+        self
+    }
+
+    /// Creates the lock key object using the builder options.
+    pub fn build(self) -> io::Result<LockKey> {
+        LockKey::open(self)
+    }
 }
 
 impl fmt::Debug for LockKey {
@@ -97,6 +951,10 @@ impl fmt::Debug for LockKey {
 pub trait LockKeyWrapper {
     /// Creates a new lock key object.
     fn new() -> Self;
+    /// Tries to create a new lock key object, failing instead of panicking when the OS handle cannot be opened.
+    fn try_new() -> io::Result<Self>
+    where
+        Self: Sized;
     /// Sets a new state for the lock key.
     fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult;
     /// Enables the lock key.
@@ -107,16 +965,424 @@ pub trait LockKeyWrapper {
     fn toggle(&self, key: LockKeys) -> LockKeyResult;
     /// Retrieves the lock key state.
     fn state(&self, key: LockKeys) -> LockKeyResult;
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Retrieves the states of the standard lock keys, skipping the ones not supported.
+    ///
+    /// Reads each key with `state` by default, the backends reading them all at once override it, e.g. for polling
+    /// loops.
+    fn states(&self) -> io::Result<LockKeyStates> {
+        let mut states = LockKeyStates::new();
+        for key in LockKeys::all() {
+            match self.state(key) {
+                Ok(state) => states.insert(key, state),
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(states)
+    }
 
-    #[test]
-    fn set() {
-        let lock_key = LockKey::new();
-        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+    /// Sets a new state for the lock key trusting `current` as its state, i.e. without reading it first.
+    ///
+    /// Nothing is sent to the OS when `current` is already the `desired` state.
+    fn set_known(
+        &self,
+        key: LockKeys,
+        current: LockKeyState,
+        desired: LockKeyState,
+    ) -> LockKeyResult {
+        if current == desired {
+            return Ok(desired);
+        }
+        self.set(key, desired)
+    }
+
+    /// Sets the lock key to the state computed by `f` from its current state, returning the previous state.
+    ///
+    /// Generalizes `toggle`, which is `toggle_with(key, |state| state.toggled())`, e.g. `|_| LockKeyState::Disabled`
+    /// turns the key off only when it is on.
+    fn toggle_with(
+        &self,
+        key: LockKeys,
+        f: impl FnOnce(LockKeyState) -> LockKeyState,
+    ) -> LockKeyResult
+    where
+        Self: Sized,
+    {
+        let state = self.state(key)?;
+        self.set_known(key, state, f(state))?;
+        Ok(state)
+    }
+
+    /// Toggles the lock key without reading its state first, on backends where the key press itself toggles it.
+    ///
+    /// Fails with `Unsupported` on the backends that can only set states, i.e. Xlib and IOKit.
+    fn toggle_blind(&self, key: LockKeys) -> io::Result<()> {
+        Err(unsupported_key(key, "the backend only sets states"))
+    }
+
+    /// Toggles the lock key returning its previous state, handling a read failure according to the policy.
+    ///
+    /// With `TogglePolicy::BlindOnReadFailure`, a failed read falls back to `toggle_blind`, returning `None` as the
+    /// previous state is unknown, or the read error when the backend cannot toggle blindly either.
+    fn toggle_with_policy(
+        &self,
+        key: LockKeys,
+        policy: TogglePolicy,
+    ) -> io::Result<Option<LockKeyState>> {
+        match policy {
+            TogglePolicy::StrictRead => self.toggle(key).map(Some),
+            TogglePolicy::BlindOnReadFailure => match self.state(key) {
+                Ok(state) => {
+                    self.set_known(key, state, state.toggle())?;
+                    Ok(Some(state))
+                }
+                Err(err) => self.toggle_blind(key).map(|_| None).map_err(|_| err),
+            },
+        }
+    }
+
+    /// Retrieves the lock key state, or `None` when it cannot be determined, e.g. for a key unsupported on the
+    /// current platform, keeping `Err` for the actual failures.
+    fn state_opt(&self, key: LockKeys) -> io::Result<Option<LockKeyState>> {
+        match self.state(key) {
+            Ok(state) => Ok(Some(state)),
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Reports the lock key state, `Unknown` when it cannot be told either because the key is unsupported or because
+    /// reading it failed, use `state_opt` to get the read error.
+    fn report(&self, key: LockKeys) -> LockKeyReport {
+        self.state_opt(key).ok().flatten().into()
+    }
+
+    /// Checks whether `set(key, desired)` would change the lock key state, i.e. whether it would send anything to
+    /// the OS, only reading the state.
+    fn would_change(&self, key: LockKeys, desired: LockKeyState) -> io::Result<bool> {
+        Ok(self.state(key)? != desired)
+    }
+
+    /// Reconciles the lock keys to the states in the `spec` snapshot, returning the keys that were changed.
+    fn apply_spec(&self, spec: &LockKeyStates) -> io::Result<Vec<LockKeys>> {
+        let mut changed = Vec::new();
+        for (key, desired) in spec.iter() {
+            let current = self.state(key)?;
+            if current != desired {
+                self.set_known(key, current, desired)?;
+                changed.push(key);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Sets each lock key to its state, timing every change, e.g. to spot the slow keys on a remote X display.
+    ///
+    /// A failing change does not stop the others. Each result comes with how long its `set` took, including reading
+    /// the current state.
+    fn set_many_timed(&self, changes: &[LockChange]) -> Vec<(LockKeys, LockKeyResult, Duration)> {
+        changes
+            .iter()
+            .map(|change| {
+                let start = Instant::now();
+                let result = self.set(change.key, change.state);
+                (change.key, result, start.elapsed())
+            })
+            .collect()
+    }
+
+    /// Sets the target lock keys to the state of the reference one, returning the keys that were changed, e.g. Capital
+    /// and Scrolling Lock to whatever Number Lock is.
+    fn match_all_to(&self, reference: LockKeys, targets: &[LockKeys]) -> io::Result<Vec<LockKeys>> {
+        let desired = self.state(reference)?;
+        let spec: LockKeyStates = targets.iter().map(|&key| (key, desired)).collect();
+        self.apply_spec(&spec)
+    }
+
+    /// Applies the changes all or nothing, rolling the changed lock keys back to their previous states when one fails.
+    ///
+    /// The rollback is best-effort: its own failures are ignored, returning the error of the failed change, and on
+    /// backends where a failing set may leave the key half changed (e.g. a lost key release event) it cannot tell.
+    fn apply_transactional(&self, changes: &[LockChange]) -> io::Result<()> {
+        let mut snapshot = Vec::with_capacity(changes.len());
+        for change in changes {
+            snapshot.push(self.state(change.key)?);
+        }
+        for (i, (change, &current)) in changes.iter().zip(&snapshot).enumerate() {
+            if let Err(err) = self.set_known(change.key, current, change.state) {
+                for (change, &state) in changes[..i].iter().zip(&snapshot).rev() {
+                    let _ = self.set(change.key, state);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the changes only when the current states match the `expected` snapshot, returning whether they were
+    /// applied, e.g. so a reconcile loop does not overwrite a concurrent toggle by the user.
+    ///
+    /// Only the keys in `expected` are compared. It is not atomic with respect to the OS: a change made between the
+    /// comparison and the changes is still overwritten.
+    fn compare_and_set(
+        &self,
+        expected: &LockKeyStates,
+        changes: &[LockChange],
+    ) -> io::Result<bool> {
+        for (key, state) in expected.iter() {
+            if self.state(key)? != state {
+                return Ok(false);
+            }
+        }
+        for change in changes {
+            let current = match expected.get(change.key) {
+                Some(current) => current,
+                None => self.state(change.key)?,
+            };
+            self.set_known(change.key, current, change.state)?;
+        }
+        Ok(true)
+    }
+
+    /// Lists the lock keys whose state differs from the `spec` without changing anything, as `(key, desired, actual)`.
+    fn drift(
+        &self,
+        spec: &[(LockKeys, LockKeyState)],
+    ) -> io::Result<Vec<(LockKeys, LockKeyState, LockKeyState)>> {
+        let mut drift = Vec::new();
+        for &(key, desired) in spec {
+            let actual = self.state(key)?;
+            if actual != desired {
+                drift.push((key, desired, actual));
+            }
+        }
+        Ok(drift)
+    }
+
+    /// Reconciles the standard lock keys to the profile stored in a TOML file, returning the keys that were changed.
+    #[cfg(feature = "serde")]
+    fn apply_profile_file(&self, path: &Path) -> io::Result<Vec<LockKeys>> {
+        self.apply_spec(&LockKeyProfile::load(path)?.states())
+    }
+
+    /// Reads the state of every lock key, reporting the ones unsupported on the current platform without reaching the
+    /// backend.
+    fn probe(&self) -> ProbeReport {
+        LOCK_KEYS
+            .into_iter()
+            .map(|key| {
+                let result = check_supported(key).and_then(|_| self.state(key));
+                (key, result)
+            })
+            .collect()
+    }
+
+    /// Retrieves the lock keys the backend can actually handle, the runtime counterpart of `LockKeys::is_supported`
+    /// also excluding the keys missing from the current layout or keyboard, i.e. whose state read fails with
+    /// `Unsupported`.
+    fn supported_keys(&self) -> Vec<LockKeys> {
+        LOCK_KEYS
+            .into_iter()
+            .filter(|&key| {
+                key.is_supported()
+                    && !matches!(self.state(key), Err(err) if err.kind() == io::ErrorKind::Unsupported)
+            })
+            .collect()
+    }
+
+    /// Probes the lock keys at every interval, for live diagnostics, see `ProbeStream`.
+    fn probe_stream(&self, interval: Duration) -> ProbeStream<'_, Self> {
+        ProbeStream::new(self, interval)
+    }
+
+    /// Sets every lock key in the snapshot to the opposite of its snapshot state, e.g. of the one retrieved by
+    /// `states()`.
+    fn apply_inverse(&self, snapshot: &LockKeyStates) -> io::Result<()> {
+        for (key, state) in snapshot.toggled().iter() {
+            self.set(key, state)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the lock key state and sets it again, forcing the keyboard LED to match it when they diverged (e.g.
+    /// after a suspend/resume), returning the state.
+    ///
+    /// Xlib and IOKit re-apply the state to the LED. On Windows the LED follows the toggle state maintained by the
+    /// OS and nothing is sent, as the key press would flip both.
+    fn resync(&self, key: LockKeys) -> LockKeyResult {
+        let state = self.state(key)?;
+        self.set(key, state)
+    }
+
+    /// Saves the lock key state returning a guard that restores it when dropped.
+    fn guard(&self, key: LockKeys) -> io::Result<LockKeyGuard<'_, Self>>
+    where
+        Self: Sized,
+    {
+        LockKeyGuard::new(self, key)
+    }
+}
+
+/// A guard that restores the saved lock key state when dropped, including during a panic unwind.
+#[derive(Debug)]
+pub struct LockKeyGuard<'a, T: LockKeyWrapper> {
+    lock_key: &'a T,
+    key: LockKeys,
+    state: LockKeyState,
+}
+
+impl<'a, T: LockKeyWrapper> LockKeyGuard<'a, T> {
+    /// Saves the current state of the lock key.
+    pub fn new(lock_key: &'a T, key: LockKeys) -> io::Result<Self> {
+        let state = lock_key.state(key)?;
+        Ok(LockKeyGuard {
+            lock_key,
+            key,
+            state,
+        })
+    }
+
+    /// Retrieves the saved lock key state.
+    pub fn state(&self) -> LockKeyState {
+        self.state
+    }
+}
+
+impl<T: LockKeyWrapper> Drop for LockKeyGuard<'_, T> {
+    fn drop(&mut self) {
+        let _ = self.lock_key.set(self.key, self.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    /// A lock key object keeping the standard lock key states in memory, shared by its clones.
+    #[derive(Clone, Debug)]
+    struct MockLockKey {
+        states: Arc<Mutex<LockKeyStates>>,
+        leds: Arc<Mutex<LockKeyStates>>,
+        reads: Arc<AtomicUsize>,
+        writes: Arc<AtomicUsize>,
+        fail_reads: Arc<AtomicBool>,
+        failing_writes: Arc<Mutex<LockKeySet>>,
+    }
+
+    impl MockLockKey {
+        /// Retrieves the state shown by the lock key LED, which follows every `set`.
+        fn led(&self, key: LockKeys) -> Option<LockKeyState> {
+            self.leds.lock().unwrap().get(key)
+        }
+
+        /// Makes the lock key LED diverge from its state, e.g. as after a suspend/resume.
+        fn diverge_led(&self, key: LockKeys) {
+            let mut leds = self.leds.lock().unwrap();
+            let led = leds.get(key).unwrap();
+            leds.insert(key, led.toggle());
+        }
+
+        fn reads(&self) -> usize {
+            self.reads.load(Ordering::SeqCst)
+        }
+
+        fn writes(&self) -> usize {
+            self.writes.load(Ordering::SeqCst)
+        }
+
+        /// Makes `state` fail as a backend unable to read the lock keys, while `set` keeps working.
+        fn fail_reads(&self) {
+            self.fail_reads.store(true, Ordering::SeqCst);
+        }
+
+        /// Makes `set` fail for the lock key, while `state` keeps working.
+        fn fail_writes(&self, key: LockKeys) {
+            self.failing_writes.lock().unwrap().insert(key);
+        }
+    }
+
+    impl LockKeyWrapper for MockLockKey {
+        fn new() -> Self {
+            let states: LockKeyStates = LockKeys::all()
+                .into_iter()
+                .map(|key| (key, LockKeyState::Disabled))
+                .collect();
+            MockLockKey {
+                states: Arc::new(Mutex::new(states)),
+                leds: Arc::new(Mutex::new(states)),
+                reads: Arc::default(),
+                writes: Arc::default(),
+                fail_reads: Arc::default(),
+                failing_writes: Arc::default(),
+            }
+        }
+
+        fn try_new() -> io::Result<Self> {
+            Ok(Self::new())
+        }
+
+        fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+            let mut states = self.states.lock().unwrap();
+            states
+                .get(key)
+                .ok_or_else(|| unsupported_key(key, "not in the mock states"))?;
+            if self.failing_writes.lock().unwrap().contains(key) {
+                return Err(io::Error::other("write failed"));
+            }
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            states.insert(key, state);
+            self.leds.lock().unwrap().insert(key, state);
+            Ok(state)
+        }
+
+        fn enable(&self, key: LockKeys) -> LockKeyResult {
+            self.set(key, LockKeyState::Enabled)
+        }
+
+        fn disable(&self, key: LockKeys) -> LockKeyResult {
+            self.set(key, LockKeyState::Disabled)
+        }
+
+        fn toggle(&self, key: LockKeys) -> LockKeyResult {
+            let state = self.state(key)?;
+            self.set(key, state.toggle())?;
+            Ok(state)
+        }
+
+        fn toggle_blind(&self, key: LockKeys) -> io::Result<()> {
+            let mut states = self.states.lock().unwrap();
+            let state = states
+                .get(key)
+                .ok_or_else(|| unsupported_key(key, "not in the mock states"))?;
+            self.writes.fetch_add(1, Ordering::SeqCst);
+            states.insert(key, state.toggle());
+            self.leds.lock().unwrap().insert(key, state.toggle());
+            Ok(())
+        }
+
+        fn state(&self, key: LockKeys) -> LockKeyResult {
+            let states = self.states.lock().unwrap();
+            self.reads.fetch_add(1, Ordering::SeqCst);
+            if self.fail_reads.load(Ordering::SeqCst) {
+                return Err(io::Error::other("read failed"));
+            }
+            states
+                .get(key)
+                .ok_or_else(|| unsupported_key(key, "not in the mock states"))
+        }
+    }
+
+    #[test]
+    fn set() {
+        let lock_key = LockKey::new();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
         assert_eq!(
             lock_key
                 .set(LockKeys::CapitalLock, LockKeyState::Disabled)
@@ -137,15 +1403,12 @@ mod tests {
             lock_key.state(LockKeys::CapitalLock).unwrap(),
             LockKeyState::Enabled
         );
-        lock_key
-            .set(LockKeys::CapitalLock, old_lock_key_state)
-            .unwrap();
     }
 
     #[test]
     fn enable() {
         let lock_key = LockKey::new();
-        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
         lock_key.disable(LockKeys::CapitalLock).unwrap();
         assert_eq!(
             lock_key.state(LockKeys::CapitalLock).unwrap(),
@@ -159,15 +1422,12 @@ mod tests {
             lock_key.state(LockKeys::CapitalLock).unwrap(),
             LockKeyState::Enabled
         );
-        lock_key
-            .set(LockKeys::CapitalLock, old_lock_key_state)
-            .unwrap();
     }
 
     #[test]
     fn disable() {
         let lock_key = LockKey::new();
-        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
         lock_key.enable(LockKeys::CapitalLock).unwrap();
         assert_eq!(
             lock_key.state(LockKeys::CapitalLock).unwrap(),
@@ -181,15 +1441,12 @@ mod tests {
             lock_key.state(LockKeys::CapitalLock).unwrap(),
             LockKeyState::Disabled
         );
-        lock_key
-            .set(LockKeys::CapitalLock, old_lock_key_state)
-            .unwrap();
     }
 
     #[test]
     fn toggle() {
         let lock_key = LockKey::new();
-        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
         lock_key.enable(LockKeys::CapitalLock).unwrap();
         assert_eq!(
             lock_key.toggle(LockKeys::CapitalLock).unwrap(),
@@ -199,15 +1456,12 @@ mod tests {
             lock_key.toggle(LockKeys::CapitalLock).unwrap(),
             LockKeyState::Disabled
         );
-        lock_key
-            .set(LockKeys::CapitalLock, old_lock_key_state)
-            .unwrap();
     }
 
     #[test]
     fn state() {
         let lock_key = LockKey::new();
-        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
         lock_key.enable(LockKeys::CapitalLock).unwrap();
         assert_eq!(
             lock_key.state(LockKeys::CapitalLock).unwrap(),
@@ -218,8 +1472,2547 @@ mod tests {
             lock_key.toggle(LockKeys::CapitalLock).unwrap(),
             LockKeyState::Disabled
         );
-        lock_key
-            .set(LockKeys::CapitalLock, old_lock_key_state)
-            .unwrap();
+    }
+
+    #[test]
+    fn guard() {
+        let lock_key = LockKey::new();
+        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+            lock_key.toggle(LockKeys::CapitalLock).unwrap();
+            panic!("deliberately failing while the state is changed");
+        }));
+        assert!(result.is_err());
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            old_lock_key_state
+        );
+    }
+
+    #[test]
+    fn lock_key_state_all() {
+        assert_eq!(
+            LockKeyState::all(),
+            [LockKeyState::Enabled, LockKeyState::Disabled]
+        );
+    }
+
+    #[test]
+    fn lock_key_state_toggled() {
+        for state in LockKeyState::all() {
+            assert_eq!(state.toggled(), state.toggle());
+            assert_eq!(!state, state.toggled());
+            assert_ne!(state.toggled(), state);
+        }
+    }
+
+    #[test]
+    fn platform_constant_name() {
+        let names: Vec<_> = LOCK_KEYS
+            .into_iter()
+            .map(LockKeys::platform_constant_name)
+            .collect();
+        #[cfg(target_os = "linux")]
+        let expected = [
+            "XK_Caps_Lock",
+            "XK_Num_Lock",
+            "XK_Scroll_Lock",
+            "XK_Shift_Lock",
+            "XK_Insert",
+        ];
+        #[cfg(target_os = "windows")]
+        let expected = ["VK_CAPITAL", "VK_NUMLOCK", "VK_SCROLL", "", "VK_INSERT"];
+        #[cfg(target_os = "macos")]
+        let expected = ["kIOHIDCapsLockState", "kIOHIDNumLockState", "", "", ""];
+        #[cfg(target_os = "android")]
+        let expected = [""; 5];
+        assert_eq!(names, expected);
+    }
+
+    #[test]
+    fn lock_key_groups() {
+        assert_eq!(LockKeys::indicators(), LockKeys::all());
+        assert_eq!(LockKeys::modifiers(), [LockKeys::ShiftLock]);
+        let groups: LockKeySet = LockKeys::indicators()
+            .iter()
+            .chain(LockKeys::modifiers())
+            .copied()
+            .collect();
+        assert_eq!(
+            groups.len(),
+            LockKeys::indicators().len() + LockKeys::modifiers().len()
+        );
+        assert!(!groups.contains(LockKeys::Insert));
+    }
+
+    #[test]
+    fn platform_default() {
+        let defaults: Vec<_> = LOCK_KEYS
+            .into_iter()
+            .map(LockKeys::platform_default)
+            .collect();
+        #[cfg(target_os = "windows")]
+        let number_lock = LockKeyState::Enabled;
+        #[cfg(not(target_os = "windows"))]
+        let number_lock = LockKeyState::Disabled;
+        assert_eq!(
+            defaults,
+            [
+                LockKeyState::Disabled,
+                number_lock,
+                LockKeyState::Disabled,
+                LockKeyState::Disabled,
+                LockKeyState::Disabled,
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "android")]
+    fn android_unsupported() {
+        let lock_key = LockKey::try_new().unwrap();
+        for key in LOCK_KEYS {
+            assert!(!key.is_supported());
+            let err = lock_key.state(key).unwrap_err();
+            assert_eq!(
+                UnsupportedKeyError::from_io_error(&err).unwrap().reason(),
+                NO_LOCK_KEYS_ON_ANDROID
+            );
+            assert_eq!(
+                lock_key.set(key, LockKeyState::Enabled).unwrap_err().kind(),
+                io::ErrorKind::Unsupported
+            );
+        }
+        assert!(lock_key.supported_keys().is_empty());
+    }
+
+    #[test]
+    fn lock_key_state_describe() {
+        assert_eq!(
+            LockKeyState::Enabled.describe(LockKeys::CapitalLock),
+            "Caps Lock is currently enabled."
+        );
+        assert_eq!(
+            LockKeyState::Disabled.describe(LockKeys::NumberLock),
+            "Num Lock is currently disabled."
+        );
+        assert_eq!(
+            LockKeyState::Enabled.describe(LockKeys::ScrollingLock),
+            "Scroll Lock is currently enabled."
+        );
+    }
+
+    #[test]
+    fn lock_key_state_merge() {
+        use LockKeyState::{Disabled, Enabled};
+
+        for (a, b, any, all) in [
+            (Enabled, Enabled, Enabled, Enabled),
+            (Enabled, Disabled, Enabled, Disabled),
+            (Disabled, Enabled, Enabled, Disabled),
+            (Disabled, Disabled, Disabled, Disabled),
+        ] {
+            assert_eq!(a.merge(b, MergePolicy::Any), any);
+            assert_eq!(a.merge(b, MergePolicy::All), all);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn sysfs_led_paths() {
+        let sys = std::env::temp_dir().join(format!("lock_keys-sysfs-{}", std::process::id()));
+        let leds = sys.join("class/leds");
+        for name in [
+            "input3::capslock",
+            "input3::numlock",
+            "input3::scrolllock",
+            "input12::capslock",
+            "input3::kana",
+            "phy0-led",
+        ] {
+            std::fs::create_dir_all(leds.join(name)).unwrap();
+        }
+        let paths = |key| evdev::sysfs_led_paths_in(&sys, key);
+        assert_eq!(
+            paths(LockKeys::CapitalLock),
+            [
+                leds.join("input12::capslock/brightness"),
+                leds.join("input3::capslock/brightness")
+            ]
+        );
+        assert_eq!(
+            paths(LockKeys::NumberLock),
+            [leds.join("input3::numlock/brightness")]
+        );
+        assert_eq!(
+            paths(LockKeys::ScrollingLock),
+            [leds.join("input3::scrolllock/brightness")]
+        );
+        assert!(paths(LockKeys::ShiftLock).is_empty());
+        assert!(paths(LockKeys::Insert).is_empty());
+        std::fs::remove_dir_all(&sys).unwrap();
+        assert!(paths(LockKeys::CapitalLock).is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn verify_led() {
+        let sys = std::env::temp_dir().join(format!("lock_keys-verify-{}", std::process::id()));
+        let leds = sys.join("class/leds");
+        for (name, brightness) in [("input3::capslock", "1\n"), ("input7::capslock", "0\n")] {
+            std::fs::create_dir_all(leds.join(name)).unwrap();
+            std::fs::write(leds.join(name).join("brightness"), brightness).unwrap();
+        }
+        let verify = |state| {
+            let observed = evdev::sysfs_led_states_in(&sys, LockKeys::CapitalLock)?;
+            linux::check_observed(LockKeys::CapitalLock, state, observed)
+        };
+        // One keyboard LED did not follow the change.
+        assert_eq!(
+            verify(LockKeyState::Enabled).unwrap_err().to_string(),
+            "CapitalLock is disabled after setting it enabled"
+        );
+        std::fs::write(leds.join("input7::capslock/brightness"), "1\n").unwrap();
+        assert_eq!(
+            verify(LockKeyState::Enabled).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert!(verify(LockKeyState::Disabled).is_err());
+        assert_eq!(
+            evdev::sysfs_led_states_in(&sys, LockKeys::NumberLock)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        std::fs::remove_dir_all(&sys).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn aggregate_states() {
+        let device = |caps| -> io::Result<LockKeyStates> {
+            Ok([(LockKeys::CapitalLock, caps)].into_iter().collect())
+        };
+        let devices = || {
+            vec![
+                device(LockKeyState::Enabled),
+                Err(io::Error::from(io::ErrorKind::PermissionDenied)),
+                device(LockKeyState::Disabled),
+            ]
+        };
+        assert_eq!(
+            evdev::aggregate_states(devices(), LockKeys::CapitalLock, MergePolicy::Any).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            evdev::aggregate_states(devices(), LockKeys::CapitalLock, MergePolicy::All).unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(
+            evdev::aggregate_states(
+                vec![Err(io::Error::from(io::ErrorKind::PermissionDenied))],
+                LockKeys::CapitalLock,
+                MergePolicy::Any
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        assert_eq!(
+            evdev::aggregate_states(vec![], LockKeys::CapitalLock, MergePolicy::Any)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+        // Called without a lock key object, so without X.
+        assert_eq!(
+            LockKey::aggregated_state(LockKeys::Insert, MergePolicy::Any)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn lock_key_state_compare() {
+        assert_eq!(
+            LockKeyState::Enabled.compare(LockKeyState::Enabled),
+            Change::NoChange
+        );
+        assert_eq!(
+            LockKeyState::Disabled.compare(LockKeyState::Enabled),
+            Change::WouldChange {
+                from: LockKeyState::Disabled,
+                to: LockKeyState::Enabled
+            }
+        );
+    }
+
+    #[test]
+    fn parse_state() {
+        assert_eq!("enabled".parse(), Ok(LockKeyState::Enabled));
+        assert_eq!(
+            LockKeyState::try_from("disabled"),
+            Ok(LockKeyState::Disabled)
+        );
+        assert_eq!(String::from(LockKeyState::Enabled), "enabled");
+        let err = "Enabled".parse::<LockKeyState>().unwrap_err();
+        assert_eq!(err.input(), "Enabled");
+        assert_eq!(err.to_string(), "invalid lock key state \"Enabled\"");
+    }
+
+    #[test]
+    fn parse_bool_like() {
+        for token in [
+            "enabled", "enable", "on", "true", "t", "yes", "y", "1", "YES", " On ",
+        ] {
+            assert_eq!(
+                LockKeyState::parse_bool_like(token),
+                Some(LockKeyState::Enabled),
+                "{:?}",
+                token
+            );
+        }
+        for token in [
+            "disabled", "disable", "off", "false", "f", "no", "n", "0", "No", "\tOFF\n",
+        ] {
+            assert_eq!(
+                LockKeyState::parse_bool_like(token),
+                Some(LockKeyState::Disabled),
+                "{:?}",
+                token
+            );
+        }
+        for token in ["", " ", "2", "-1", "yess", "o", "nope", "enabledd", "1.0"] {
+            assert_eq!(LockKeyState::parse_bool_like(token), None, "{:?}", token);
+        }
+    }
+
+    #[test]
+    fn lock_keys_iter() {
+        let mut iter = LockKeys::iter();
+        assert_eq!(iter.size_hint(), (3, Some(3)));
+        assert_eq!(iter.next(), Some(LockKeys::CapitalLock));
+        assert_eq!(iter.len(), 2);
+        assert_eq!(
+            LockKeys::iter().collect::<Vec<_>>(),
+            LockKeys::all().to_vec()
+        );
+        assert_eq!(
+            LockKeys::iter().rev().collect::<Vec<_>>(),
+            vec![
+                LockKeys::ScrollingLock,
+                LockKeys::NumberLock,
+                LockKeys::CapitalLock
+            ]
+        );
+    }
+
+    #[test]
+    fn lock_key_set() {
+        let mut set = LockKeySet::new();
+        assert!(set.is_empty());
+        set.insert(LockKeys::CapitalLock);
+        set.insert(LockKeys::CapitalLock);
+        assert!(set.contains(LockKeys::CapitalLock));
+        assert!(!set.contains(LockKeys::NumberLock));
+        assert_eq!(set.len(), 1);
+        set.remove(LockKeys::CapitalLock);
+        assert!(set.is_empty());
+
+        let caps_num: LockKeySet = [LockKeys::CapitalLock, LockKeys::NumberLock]
+            .into_iter()
+            .collect();
+        let num_scroll: LockKeySet = [LockKeys::NumberLock, LockKeys::ScrollingLock]
+            .into_iter()
+            .collect();
+        assert_eq!(
+            (caps_num | num_scroll).iter().collect::<Vec<_>>(),
+            LockKeys::all().to_vec()
+        );
+        assert_eq!(
+            caps_num & num_scroll,
+            LockKeySet::from(LockKeys::NumberLock)
+        );
+        assert_eq!(
+            caps_num - num_scroll,
+            LockKeySet::from(LockKeys::CapitalLock)
+        );
+    }
+
+    #[test]
+    fn lock_key_set_complement() {
+        let set = LockKeySet::from(LockKeys::CapitalLock);
+        let expected: &[LockKeys] = if cfg!(target_os = "linux") {
+            &[
+                LockKeys::NumberLock,
+                LockKeys::ScrollingLock,
+                LockKeys::ShiftLock,
+                LockKeys::Insert,
+            ]
+        } else if cfg!(target_os = "windows") {
+            &[
+                LockKeys::NumberLock,
+                LockKeys::ScrollingLock,
+                LockKeys::Insert,
+            ]
+        } else {
+            &[LockKeys::NumberLock]
+        };
+        assert_eq!(set.complement().iter().collect::<Vec<_>>(), expected);
+        assert!((set | set.complement()).complement().is_empty());
+        assert_eq!(
+            LockKeySet::new().complement().complement(),
+            LockKeySet::new()
+        );
+    }
+
+    #[test]
+    fn insert_opt_in() {
+        assert!(!LockKeys::all().contains(&LockKeys::Insert));
+        assert_eq!(LockKeys::Insert.is_supported(), !cfg!(target_os = "macos"));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn insert() {
+        let lock_key = LockKey::new();
+        let guard = lock_key.guard(LockKeys::Insert).unwrap();
+        assert_eq!(lock_key.toggle(LockKeys::Insert).unwrap(), guard.state());
+        assert_eq!(
+            lock_key.state(LockKeys::Insert).unwrap(),
+            guard.state().toggle()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn insert_layout() {
+        // Default layouts do not map Insert to a lock modifier.
+        let lock_key = LockKey::new();
+        match lock_key.state(LockKeys::Insert) {
+            Ok(_) => {}
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::Unsupported),
+        }
+    }
+
+    #[test]
+    fn exit_codes() {
+        for (kind, code) in [
+            (io::ErrorKind::NotFound, 2),
+            (io::ErrorKind::PermissionDenied, 13),
+            (io::ErrorKind::InvalidInput, 22),
+            (io::ErrorKind::BrokenPipe, 32),
+            (io::ErrorKind::Unsupported, 95),
+            (io::ErrorKind::NotConnected, 107),
+            (io::ErrorKind::TimedOut, 110),
+            (io::ErrorKind::Other, 1),
+            (io::ErrorKind::Interrupted, 1),
+        ] {
+            assert_eq!(error_exit_code(&io::Error::from(kind)), code);
+        }
+        assert_eq!(
+            error_exit_code(&unsupported_key(LockKeys::ShiftLock, UNMAPPED_ON_LAYOUT)),
+            95
+        );
+    }
+
+    #[test]
+    fn toggle_with() {
+        let lock_key = MockLockKey::new();
+        assert_eq!(
+            lock_key
+                .toggle_with(LockKeys::CapitalLock, |state| state.toggled())
+                .unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            lock_key
+                .toggle_with(LockKeys::CapitalLock, |_| LockKeyState::Disabled)
+                .unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(lock_key.writes(), 2);
+        // Turning off a key already off leaves it alone.
+        assert_eq!(
+            lock_key
+                .toggle_with(LockKeys::CapitalLock, |_| LockKeyState::Disabled)
+                .unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(lock_key.writes(), 2);
+    }
+
+    #[test]
+    fn toggle_policy() {
+        let lock_key = MockLockKey::new();
+        assert_eq!(
+            lock_key
+                .toggle_with_policy(LockKeys::CapitalLock, TogglePolicy::BlindOnReadFailure)
+                .unwrap(),
+            Some(LockKeyState::Disabled)
+        );
+        lock_key.fail_reads();
+        assert_eq!(
+            lock_key
+                .toggle_with_policy(LockKeys::CapitalLock, TogglePolicy::StrictRead)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Other
+        );
+        assert_eq!(lock_key.writes(), 1);
+        assert_eq!(
+            lock_key
+                .toggle_with_policy(LockKeys::CapitalLock, TogglePolicy::BlindOnReadFailure)
+                .unwrap(),
+            None
+        );
+        assert_eq!(lock_key.writes(), 2);
+        assert_eq!(
+            lock_key.states.lock().unwrap().get(LockKeys::CapitalLock),
+            Some(LockKeyState::Disabled)
+        );
+        assert_eq!(
+            lock_key
+                .toggle_with_policy(LockKeys::ShiftLock, TogglePolicy::BlindOnReadFailure)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn requires_privileges() {
+        let lock_key = LockKey::new();
+        assert_eq!(
+            lock_key.requires_privileges(),
+            cfg!(target_os = "macos"),
+            "only IOKit needs permissions"
+        );
+    }
+
+    #[test]
+    fn unsupported_key_error() {
+        let err = unsupported_key(LockKeys::ScrollingLock, UNMAPPED_ON_LAYOUT);
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        let detail = UnsupportedKeyError::from_io_error(&err).unwrap();
+        assert_eq!(detail.key(), LockKeys::ScrollingLock);
+        assert_eq!(detail.platform(), std::env::consts::OS);
+        assert_eq!(detail.reason(), "key unmapped on current layout");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "ScrollingLock is not supported on {}: key unmapped on current layout",
+                std::env::consts::OS
+            )
+        );
+        assert!(UnsupportedKeyError::from_io_error(&io::Error::other("read failed")).is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn unsupported_key_error_scroll_lock() {
+        let lock_key = LockKey::new();
+        let err = lock_key.state(LockKeys::ScrollingLock).unwrap_err();
+        let detail = UnsupportedKeyError::from_io_error(&err).unwrap();
+        assert_eq!(detail.key(), LockKeys::ScrollingLock);
+        assert_eq!(detail.platform(), "macos");
+        assert_eq!(detail.reason(), "not exposed by IOKit");
+    }
+
+    #[test]
+    fn state_opt() {
+        let lock_key = MockLockKey::new();
+        assert_eq!(
+            lock_key.state_opt(LockKeys::CapitalLock).unwrap(),
+            Some(LockKeyState::Disabled)
+        );
+        assert_eq!(lock_key.state_opt(LockKeys::ShiftLock).unwrap(), None);
+        lock_key.fail_reads();
+        assert_eq!(
+            lock_key
+                .state_opt(LockKeys::CapitalLock)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn would_change() {
+        let lock_key = MockLockKey::new();
+        assert!(!lock_key
+            .would_change(LockKeys::CapitalLock, LockKeyState::Disabled)
+            .unwrap());
+        assert!(lock_key
+            .would_change(LockKeys::CapitalLock, LockKeyState::Enabled)
+            .unwrap());
+        assert_eq!((lock_key.reads(), lock_key.writes()), (2, 0));
+        assert_eq!(
+            lock_key
+                .would_change(LockKeys::ShiftLock, LockKeyState::Enabled)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn apply_spec() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        let spec: LockKeyStates = [
+            (LockKeys::CapitalLock, LockKeyState::Enabled),
+            (LockKeys::NumberLock, LockKeyState::Enabled),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            lock_key.apply_spec(&spec).unwrap(),
+            vec![LockKeys::CapitalLock]
+        );
+        assert_eq!(lock_key.apply_spec(&spec).unwrap(), vec![]);
+        assert_eq!(
+            lock_key.state(LockKeys::ScrollingLock).unwrap(),
+            LockKeyState::Disabled
+        );
+    }
+
+    #[test]
+    fn set_many_timed() {
+        let lock_key = MockLockKey::new();
+        lock_key.fail_writes(LockKeys::ScrollingLock);
+        let changes = [
+            LockChange::new(LockKeys::CapitalLock, LockKeyState::Enabled),
+            LockChange::new(LockKeys::ScrollingLock, LockKeyState::Enabled),
+        ];
+        let start = Instant::now();
+        let results = lock_key.set_many_timed(&changes);
+        let elapsed = start.elapsed();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, LockKeys::CapitalLock);
+        assert_eq!(results[0].1.as_ref().unwrap(), &LockKeyState::Enabled);
+        assert_eq!(results[1].0, LockKeys::ScrollingLock);
+        assert!(results[1].1.is_err());
+        assert!(results.iter().map(|result| result.2).sum::<Duration>() <= elapsed);
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn match_all_to() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        lock_key.enable(LockKeys::ScrollingLock).unwrap();
+        let targets = [LockKeys::CapitalLock, LockKeys::ScrollingLock];
+        assert_eq!(
+            lock_key
+                .match_all_to(LockKeys::NumberLock, &targets)
+                .unwrap(),
+            vec![LockKeys::CapitalLock]
+        );
+        for key in targets {
+            assert_eq!(lock_key.state(key).unwrap(), LockKeyState::Enabled);
+        }
+        lock_key.disable(LockKeys::NumberLock).unwrap();
+        assert_eq!(
+            lock_key
+                .match_all_to(LockKeys::NumberLock, &targets)
+                .unwrap(),
+            targets
+        );
+    }
+
+    #[test]
+    fn apply_transactional() {
+        let lock_key = MockLockKey::new();
+        let changes = [
+            LockChange::new(LockKeys::CapitalLock, LockKeyState::Enabled),
+            LockChange::new(LockKeys::NumberLock, LockKeyState::Enabled),
+        ];
+        lock_key.fail_writes(LockKeys::NumberLock);
+        assert_eq!(
+            lock_key.apply_transactional(&changes).unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+        assert_eq!(lock_key.writes(), 2);
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        lock_key
+            .failing_writes
+            .lock()
+            .unwrap()
+            .remove(LockKeys::NumberLock);
+        lock_key.apply_transactional(&changes).unwrap();
+        for change in changes {
+            assert_eq!(lock_key.state(change.key).unwrap(), change.state);
+        }
+    }
+
+    #[test]
+    fn compare_and_set() {
+        let lock_key = MockLockKey::new();
+        let expected: LockKeyStates = [(LockKeys::CapitalLock, LockKeyState::Disabled)]
+            .into_iter()
+            .collect();
+        let changes = [
+            LockChange::new(LockKeys::CapitalLock, LockKeyState::Enabled),
+            LockChange::new(LockKeys::NumberLock, LockKeyState::Enabled),
+        ];
+        assert!(lock_key.compare_and_set(&expected, &changes).unwrap());
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        // Capital Lock is no longer disabled, so nothing changes.
+        let writes = lock_key.writes();
+        let changes = [LockChange::new(
+            LockKeys::NumberLock,
+            LockKeyState::Disabled,
+        )];
+        assert!(!lock_key.compare_and_set(&expected, &changes).unwrap());
+        assert_eq!(lock_key.writes(), writes);
+        assert_eq!(
+            lock_key.state(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn drift() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        let spec = [
+            (LockKeys::CapitalLock, LockKeyState::Enabled),
+            (LockKeys::NumberLock, LockKeyState::Enabled),
+            (LockKeys::ScrollingLock, LockKeyState::Disabled),
+        ];
+        assert_eq!(
+            lock_key.drift(&spec).unwrap(),
+            vec![(
+                LockKeys::CapitalLock,
+                LockKeyState::Enabled,
+                LockKeyState::Disabled
+            )]
+        );
+        assert_eq!(lock_key.writes(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn change_to_json() {
+        assert_eq!(
+            json::change_to_json_at(LockKeys::CapitalLock, LockKeyState::Enabled, 1700000000000),
+            r#"{"key":"capital_lock","state":"enabled","ts":1700000000000}"#
+        );
+        assert!(
+            super::change_to_json(LockKeys::NumberLock, LockKeyState::Disabled)
+                .starts_with(r#"{"key":"number_lock","state":"disabled","ts":"#)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "arbitrary")]
+    fn arbitrary() {
+        use arbitrary::{Arbitrary, Unstructured};
+
+        // Every 4 bytes make the `u32` picking a variant, so this goes through the whole range twice.
+        let bytes: Vec<u8> = (0..=255).chain(0..=255).collect();
+        let mut u = Unstructured::new(&bytes);
+        let keys: LockKeySet = (0..64)
+            .map(|_| LockKeys::arbitrary(&mut u).unwrap())
+            .collect();
+        assert_eq!(keys, LOCK_KEYS.into_iter().collect());
+        let states: Vec<_> = (0..64)
+            .map(|_| LockKeyState::arbitrary(&mut u).unwrap())
+            .collect();
+        assert!(states.contains(&LockKeyState::Enabled));
+        assert!(states.contains(&LockKeyState::Disabled));
+        // The same bytes always give the same values.
+        assert_eq!(
+            LockKeys::arbitrary(&mut Unstructured::new(&bytes)).unwrap(),
+            LockKeys::arbitrary(&mut Unstructured::new(&bytes)).unwrap()
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "ipc", unix))]
+    fn ipc() {
+        let path = std::env::temp_dir().join(format!("lock_keys-{}.sock", std::process::id()));
+        let server = IpcServer::bind(&path).unwrap();
+        thread::spawn(move || server.serve(&MockLockKey::new()));
+        let mut client = IpcClient::connect(&path).unwrap();
+        assert_eq!(
+            client
+                .set(LockKeys::CapitalLock, LockKeyState::Enabled)
+                .unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            client.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            client.toggle(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        // The mock has no Insert state, the server error reaches the client.
+        assert!(client.state(LockKeys::Insert).is_err());
+        assert_eq!(
+            client.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn apply_profile_file() {
+        let path =
+            std::env::temp_dir().join(format!("lock_keys-profile-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "capital_lock = true\nnum_lock = false\nscroll_lock = true\n",
+        )
+        .unwrap();
+        let lock_key = MockLockKey::new();
+        let changed = lock_key.apply_profile_file(&path);
+        std::fs::write(&path, "capital_lock = \"yes\"\n").unwrap();
+        let invalid = lock_key.apply_profile_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            changed.unwrap(),
+            vec![LockKeys::CapitalLock, LockKeys::ScrollingLock]
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(invalid.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn apply_inverse() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        let snapshot = lock_key.states().unwrap();
+        lock_key.apply_inverse(&snapshot).unwrap();
+        for (key, state) in snapshot.iter() {
+            assert_eq!(lock_key.state(key).unwrap(), state.toggle());
+        }
+        assert_eq!(lock_key.states().unwrap(), snapshot.toggled());
+    }
+
+    #[test]
+    fn resync() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        lock_key.diverge_led(LockKeys::NumberLock);
+        assert_eq!(
+            lock_key.led(LockKeys::NumberLock),
+            Some(LockKeyState::Disabled)
+        );
+        assert_eq!(
+            lock_key.resync(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            lock_key.led(LockKeys::NumberLock),
+            Some(LockKeyState::Enabled)
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn set_known() {
+        let lock_key = MockLockKey::new();
+        assert_eq!(
+            lock_key
+                .set_known(
+                    LockKeys::CapitalLock,
+                    LockKeyState::Disabled,
+                    LockKeyState::Disabled
+                )
+                .unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!((lock_key.reads(), lock_key.writes()), (0, 0));
+        assert_eq!(
+            lock_key
+                .set_known(
+                    LockKeys::CapitalLock,
+                    LockKeyState::Disabled,
+                    LockKeyState::Enabled
+                )
+                .unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!((lock_key.reads(), lock_key.writes()), (0, 1));
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn watch() {
+        let lock_key = LockKey::new();
+        let (sender, receiver) = mpsc::channel();
+        let handle = lock_key
+            .watch(WatchOptions::new(), move |key, state| {
+                let _ = sender.send((key, state));
+            })
+            .unwrap();
+        if cfg!(target_os = "linux") {
+            assert_eq!(handle.mechanism(), WatchMechanism::XkbStateNotify);
+        } else if cfg!(target_os = "windows") {
+            assert_eq!(handle.mechanism(), WatchMechanism::LowLevelKeyboardHook);
+        } else if cfg!(target_os = "macos") {
+            // Polling without the Input Monitoring permission.
+            assert!(matches!(
+                handle.mechanism(),
+                WatchMechanism::IoHidValue | WatchMechanism::Polling
+            ));
+        } else {
+            assert_eq!(handle.mechanism(), WatchMechanism::Polling);
+        }
+        let guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        lock_key.toggle(LockKeys::CapitalLock).unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (LockKeys::CapitalLock, guard.state().toggle())
+        );
+        handle.stop();
+    }
+
+    #[test]
+    fn actor() {
+        let lock_key = MockLockKey::new();
+        let owned = lock_key.clone();
+        let actor = LockKeyActor::spawn_with(move || Ok(owned)).unwrap();
+        let threads: Vec<_> = LockKeys::all()
+            .into_iter()
+            .map(|key| {
+                let client = actor.client();
+                thread::spawn(move || {
+                    assert_eq!(client.state(key).unwrap(), LockKeyState::Disabled);
+                    assert_eq!(
+                        client.set(key, LockKeyState::Enabled).unwrap(),
+                        LockKeyState::Enabled
+                    );
+                    assert_eq!(client.toggle(key).unwrap(), LockKeyState::Enabled);
+                    assert_eq!(
+                        client
+                            .set(LockKeys::ShiftLock, LockKeyState::Enabled)
+                            .unwrap_err()
+                            .kind(),
+                        io::ErrorKind::Unsupported
+                    );
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(lock_key.writes(), 6);
+        actor.join();
+    }
+
+    #[test]
+    fn actor_coalesces_sets() {
+        let lock_key = MockLockKey::new();
+        let owned = lock_key.clone();
+        let actor = LockKeyActor::spawn_with(move || Ok(owned)).unwrap();
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let client = actor.client();
+                thread::spawn(move || {
+                    client
+                        .set(LockKeys::NumberLock, LockKeyState::Enabled)
+                        .unwrap()
+                })
+            })
+            .collect();
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), LockKeyState::Enabled);
+        }
+        assert_eq!(lock_key.writes(), 1);
+    }
+
+    #[test]
+    fn actor_open_error() {
+        let err = LockKeyActor::spawn_with(|| {
+            Err::<MockLockKey, _>(io::Error::from(io::ErrorKind::NotFound))
+        })
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn watch_latency() {
+        let lock_key = LockKey::new();
+        let (sender, receiver) = mpsc::channel();
+        let _handle = lock_key
+            .watch(WatchOptions::new(), move |key, state| {
+                let _ = sender.send((key, state, Instant::now()));
+            })
+            .unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        let start = Instant::now();
+        lock_key.toggle(LockKeys::CapitalLock).unwrap();
+        let (key, _, reported) = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(key, LockKeys::CapitalLock);
+        assert!(reported - start < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn watch_polling_latency() {
+        let lock_key = MockLockKey::new();
+        let watched = lock_key.clone();
+        let (sender, receiver) = mpsc::channel();
+        let _handle = watch::watch_polling(
+            move || Ok(watched),
+            WatchOptions::new(),
+            move |_, _| {
+                let _ = sender.send(Instant::now());
+            },
+        )
+        .unwrap();
+        let start = Instant::now();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        let reported = receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(reported - start < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn enable_for() {
+        let lock_key = MockLockKey::new();
+        lock_key.disable(LockKeys::CapitalLock).unwrap();
+        let restored = lock_key.clone();
+        let handle = timer::enable_for(
+            &lock_key,
+            LockKeys::CapitalLock,
+            Duration::from_millis(100),
+            move || Ok(restored),
+        )
+        .unwrap();
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        handle.wait().unwrap();
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        // A cancelled timer leaves the lock key enabled.
+        let restored = lock_key.clone();
+        timer::enable_for(
+            &lock_key,
+            LockKeys::CapitalLock,
+            Duration::from_secs(60),
+            move || Ok(restored),
+        )
+        .unwrap()
+        .cancel();
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        // The restore errors are returned by `wait`.
+        let restored = lock_key.clone();
+        let handle = timer::enable_for(
+            &lock_key,
+            LockKeys::NumberLock,
+            Duration::from_millis(10),
+            move || Ok(restored),
+        )
+        .unwrap();
+        lock_key.fail_writes(LockKeys::NumberLock);
+        assert!(handle.wait().is_err());
+        let handle = timer::enable_for(
+            &lock_key,
+            LockKeys::CapitalLock,
+            Duration::from_millis(10),
+            || Err::<MockLockKey, _>(io::Error::other("open")),
+        )
+        .unwrap();
+        assert_eq!(handle.wait().unwrap_err().to_string(), "open");
+    }
+
+    #[test]
+    fn timer_dropped() {
+        // A dropped handle still restores.
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        let restored = lock_key.clone();
+        let (sender, receiver) = mpsc::channel();
+        drop(
+            timer::schedule(Duration::from_millis(10), move || {
+                restored.disable(LockKeys::CapitalLock)?;
+                let _ = sender.send(());
+                Ok(())
+            })
+            .unwrap(),
+        );
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+    }
+
+    #[test]
+    fn watch_polling() {
+        let lock_key = MockLockKey::new();
+        let watched = lock_key.clone();
+        let (sender, receiver) = mpsc::channel();
+        let handle = watch::watch_polling(
+            move || Ok(watched),
+            WatchOptions::new().interval(Duration::from_millis(5)),
+            move |key, state| {
+                let _ = sender.send((key, state));
+            },
+        )
+        .unwrap();
+        assert_eq!(handle.mechanism(), WatchMechanism::Polling);
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (LockKeys::NumberLock, LockKeyState::Enabled)
+        );
+        lock_key.disable(LockKeys::NumberLock).unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (LockKeys::NumberLock, LockKeyState::Disabled)
+        );
+        handle.stop();
+        assert!(receiver.recv().is_err());
+    }
+
+    #[test]
+    fn watch_key_filter() {
+        let lock_key = MockLockKey::new();
+        let (sender, receiver) = mpsc::channel();
+        let _handles: Vec<_> = [LockKeys::CapitalLock, LockKeys::NumberLock]
+            .into_iter()
+            .map(|key| {
+                let watched = lock_key.clone();
+                let sender = sender.clone();
+                watch::watch_polling(
+                    move || Ok(watched),
+                    WatchOptions::new().interval(Duration::from_millis(5)),
+                    watch::key_filter(key, move |state| {
+                        let _ = sender.send((key, state));
+                    }),
+                )
+                .unwrap()
+            })
+            .collect();
+        for key in [
+            LockKeys::NumberLock,
+            LockKeys::ScrollingLock,
+            LockKeys::CapitalLock,
+        ] {
+            lock_key.enable(key).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            [
+                (LockKeys::NumberLock, LockKeyState::Enabled),
+                (LockKeys::CapitalLock, LockKeyState::Enabled)
+            ]
+        );
+    }
+
+    #[test]
+    fn watch_last_change() {
+        let lock_key = MockLockKey::new();
+        let watched = lock_key.clone();
+        let (sender, receiver) = mpsc::channel();
+        let handle = watch::watch_polling(
+            move || Ok(watched),
+            WatchOptions::new().interval(Duration::from_millis(5)),
+            move |key, state| {
+                let _ = sender.send((key, state));
+            },
+        )
+        .unwrap();
+        assert_eq!(handle.last_change(LockKeys::NumberLock), None);
+        let start = Instant::now();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        let first = handle.last_change(LockKeys::NumberLock).unwrap();
+        assert!(first >= start);
+        thread::sleep(Duration::from_millis(10));
+        lock_key.disable(LockKeys::NumberLock).unwrap();
+        receiver.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert!(handle.last_change(LockKeys::NumberLock).unwrap() > first);
+        assert_eq!(handle.last_change(LockKeys::CapitalLock), None);
+    }
+
+    #[test]
+    fn watch_polling_coalesce() {
+        let lock_key = MockLockKey::new();
+        let watched = lock_key.clone();
+        let (sender, receiver) = mpsc::channel();
+        let _handle = watch::watch_polling(
+            move || Ok(watched),
+            WatchOptions::new()
+                .interval(Duration::from_millis(5))
+                .coalesce_window(Duration::from_millis(200)),
+            move |key, state| {
+                let _ = sender.send((key, state));
+            },
+        )
+        .unwrap();
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        lock_key.disable(LockKeys::NumberLock).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(400)).is_err());
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        lock_key.disable(LockKeys::NumberLock).unwrap();
+        thread::sleep(Duration::from_millis(20));
+        lock_key.enable(LockKeys::NumberLock).unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (LockKeys::NumberLock, LockKeyState::Enabled)
+        );
+        assert!(receiver.recv_timeout(Duration::from_millis(400)).is_err());
+    }
+
+    #[test]
+    fn watch_key_repeat_debounce() {
+        let options = WatchOptions::new()
+            .debounce_key_repeat(true)
+            .with_key_repeat(Duration::from_millis(100), Duration::from_millis(30));
+        let (sender, receiver) = mpsc::channel();
+        let mut states = MockLockKey::new().states().unwrap();
+        let mut notifier = watch::ChangeNotifier::new(
+            states,
+            options,
+            Arc::new(Mutex::new([None; LOCK_KEYS.len()])),
+            move |key, state| {
+                let _ = sender.send((key, state));
+            },
+        );
+        // A held Capital Lock flapping faster than the auto-repeat delay plus interval.
+        for _ in 0..5 {
+            states = states.toggled();
+            notifier.observe(states);
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(receiver.try_recv().is_err());
+        thread::sleep(Duration::from_millis(150));
+        notifier.flush();
+        let changes: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(
+            changes,
+            LockKeys::all().map(|key| (key, LockKeyState::Enabled))
+        );
+    }
+
+    #[test]
+    fn watch_polling_open_error() {
+        let err = watch::watch_polling(
+            || Err::<MockLockKey, _>(io::Error::from(io::ErrorKind::NotFound)),
+            WatchOptions::new(),
+            |_, _| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn report() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        assert_eq!(
+            lock_key.report(LockKeys::CapitalLock),
+            LockKeyReport::Enabled
+        );
+        assert_eq!(
+            lock_key.report(LockKeys::NumberLock),
+            LockKeyReport::Disabled
+        );
+        assert_eq!(lock_key.report(LockKeys::ShiftLock), LockKeyReport::Unknown);
+        lock_key.fail_reads();
+        assert_eq!(
+            lock_key.report(LockKeys::CapitalLock),
+            LockKeyReport::Unknown
+        );
+        assert_eq!(LockKeyReport::Enabled.state(), Some(LockKeyState::Enabled));
+        assert_eq!(LockKeyReport::Unknown.state(), None);
+    }
+
+    #[test]
+    fn supported_keys() {
+        let lock_key = MockLockKey::new();
+        let expected: Vec<_> = LockKeys::all()
+            .into_iter()
+            .filter(|key| key.is_supported())
+            .collect();
+        assert_eq!(lock_key.supported_keys(), expected);
+        // A read failure does not tell the key is unsupported.
+        lock_key.fail_reads();
+        assert_eq!(
+            lock_key.supported_keys().len(),
+            LOCK_KEYS.iter().filter(|key| key.is_supported()).count()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn supported_keys_platform() {
+        let lock_key = LockKey::new();
+        let keys = lock_key.supported_keys();
+        assert!(keys.contains(&LockKeys::CapitalLock));
+        assert!(!keys.contains(&LockKeys::ScrollingLock));
+        assert!(!keys.contains(&LockKeys::Insert));
+    }
+
+    #[test]
+    fn read_all() {
+        let seat0 = MockLockKey::new();
+        let seat1 = MockLockKey::new();
+        seat1.enable(LockKeys::NumberLock).unwrap();
+        seat1.fail_reads();
+        let seat2 = MockLockKey::new();
+        seat2.enable(LockKeys::CapitalLock).unwrap();
+        let snapshots = super::read_all(&[&seat0, &seat1, &seat2]);
+        assert_eq!(snapshots.len(), 3);
+        assert_eq!(snapshots[0].as_ref().unwrap(), &seat0.states().unwrap());
+        assert_eq!(
+            snapshots[1].as_ref().unwrap_err().kind(),
+            io::ErrorKind::Other
+        );
+        assert_eq!(
+            snapshots[2].as_ref().unwrap().get(LockKeys::CapitalLock),
+            Some(LockKeyState::Enabled)
+        );
+    }
+
+    #[test]
+    fn state_cached() {
+        let mock = MockLockKey::new();
+        let lock_key = LockKeyCache::new(mock.clone(), Duration::from_secs(3600))
+            .max_age(LockKeys::CapitalLock, Duration::ZERO);
+        assert_eq!(
+            lock_key.state_cached(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(
+            lock_key.state_cached(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        // Changed by others, only Capital Lock, whose cache is always stale, is read again.
+        mock.enable(LockKeys::CapitalLock).unwrap();
+        mock.enable(LockKeys::NumberLock).unwrap();
+        let reads = mock.reads();
+        assert_eq!(
+            lock_key.state_cached(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            lock_key.state_cached(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(mock.reads(), reads + 1);
+        lock_key.invalidate(LockKeys::NumberLock);
+        assert_eq!(
+            lock_key.state_cached(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        // The changes made through the cache are cached.
+        lock_key.toggle(LockKeys::NumberLock).unwrap();
+        let reads = mock.reads();
+        assert_eq!(
+            lock_key.state_cached(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(mock.reads(), reads);
+    }
+
+    #[test]
+    fn restore_on_panic() {
+        // Runs in a child process, as the panic hook is shared with the other tests.
+        if std::env::var_os("LOCK_KEYS_TEST_RESTORE_ON_PANIC").is_some() {
+            let mock = MockLockKey::new();
+            let snapshot = mock.states().unwrap();
+            let restored = mock.clone();
+            super::restore_on_panic(move || {
+                let _ = restored.apply_spec(&snapshot);
+            });
+            mock.enable(LockKeys::CapitalLock).unwrap();
+            assert!(panic::catch_unwind(|| panic!("with Capital Lock enabled")).is_err());
+            assert_eq!(
+                mock.state(LockKeys::CapitalLock).unwrap(),
+                LockKeyState::Disabled
+            );
+            return;
+        }
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::restore_on_panic"])
+            .env("LOCK_KEYS_TEST_RESTORE_ON_PANIC", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn undo() {
+        let mock = MockLockKey::new();
+        let lock_key = LockKeyUndo::new(mock.clone());
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        lock_key.toggle(LockKeys::NumberLock).unwrap();
+        lock_key.disable(LockKeys::CapitalLock).unwrap();
+        assert_eq!(lock_key.len(), 3);
+        let states = || mock.states().unwrap().to_bools();
+        assert_eq!(states(), [false, true, false]);
+        assert_eq!(lock_key.undo().unwrap(), Some(LockKeys::CapitalLock));
+        assert_eq!(states(), [true, true, false]);
+        assert_eq!(lock_key.undo().unwrap(), Some(LockKeys::NumberLock));
+        assert_eq!(states(), [true, false, false]);
+        assert_eq!(lock_key.undo().unwrap(), Some(LockKeys::CapitalLock));
+        assert_eq!(states(), [false, false, false]);
+        assert_eq!(lock_key.undo().unwrap(), None);
+        assert!(lock_key.is_empty());
+    }
+
+    #[test]
+    fn undo_capacity() {
+        let mock = MockLockKey::new();
+        let lock_key = LockKeyUndo::with_capacity(mock.clone(), 2);
+        for key in LockKeys::all() {
+            lock_key.enable(key).unwrap();
+        }
+        assert_eq!(lock_key.len(), 2);
+        assert_eq!(lock_key.undo().unwrap(), Some(LockKeys::ScrollingLock));
+        assert_eq!(lock_key.undo().unwrap(), Some(LockKeys::NumberLock));
+        assert_eq!(lock_key.undo().unwrap(), None);
+        assert_eq!(mock.states().unwrap().to_bools(), [true, false, false]);
+        mock.fail_writes(LockKeys::CapitalLock);
+        lock_key.toggle(LockKeys::NumberLock).unwrap();
+        assert!(lock_key.disable(LockKeys::CapitalLock).is_err());
+        assert_eq!(lock_key.len(), 1);
+    }
+
+    #[test]
+    fn probe_stream() {
+        let caps_lock = |report: &ProbeReport| {
+            let (_, result) = report
+                .iter()
+                .find(|&(key, _)| key == LockKeys::CapitalLock)
+                .unwrap();
+            result.as_ref().copied().unwrap()
+        };
+        let lock_key = MockLockKey::new();
+        let mut stream = lock_key.probe_stream(Duration::from_millis(10));
+        let first = stream.next().unwrap();
+        assert_eq!(first.iter().count(), LOCK_KEYS.len());
+        assert_eq!(caps_lock(&first), LockKeyState::Disabled);
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        let start = Instant::now();
+        let second = stream.next().unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(10));
+        assert_eq!(caps_lock(&second), LockKeyState::Enabled);
+        assert_eq!(lock_key.probe_stream(Duration::ZERO).take(3).count(), 3);
+    }
+
+    #[test]
+    fn probe() {
+        let lock_key = MockLockKey::new();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        let report = lock_key.probe();
+        assert!(report.is_supported(LockKeys::CapitalLock));
+        assert!(!report.is_supported(LockKeys::ShiftLock));
+        assert_eq!(
+            report
+                .iter()
+                .find(|(key, _)| *key == LockKeys::CapitalLock)
+                .unwrap()
+                .1
+                .as_ref()
+                .unwrap(),
+            &LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn probe_report_table() {
+        let report: ProbeReport = [
+            (LockKeys::CapitalLock, Ok(LockKeyState::Enabled)),
+            (LockKeys::NumberLock, Err(io::Error::other("read failed"))),
+            (
+                LockKeys::ScrollingLock,
+                Err(unsupported_key(LockKeys::ScrollingLock, UNMAPPED_ON_LAYOUT)),
+            ),
+            (LockKeys::ShiftLock, Ok(LockKeyState::Disabled)),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            report.to_string(),
+            "KEY            STATE               CAPABILITY\n\
+             CapitalLock    enabled             supported\n\
+             NumberLock     error: read failed  supported\n\
+             ScrollingLock  -                   unsupported\n\
+             ShiftLock      disabled            supported\n"
+        );
+    }
+
+    #[test]
+    fn plan_to() {
+        let current: LockKeyStates = [
+            (LockKeys::CapitalLock, LockKeyState::Enabled),
+            (LockKeys::NumberLock, LockKeyState::Disabled),
+            (LockKeys::ScrollingLock, LockKeyState::Disabled),
+        ]
+        .into_iter()
+        .collect();
+        assert!(current.plan_to(&current).is_empty());
+        let mut target = current;
+        target.insert(LockKeys::NumberLock, LockKeyState::Enabled);
+        assert_eq!(
+            current.plan_to(&target),
+            vec![LockChange::new(LockKeys::NumberLock, LockKeyState::Enabled)]
+        );
+        assert_eq!(
+            current.plan_to(&current.toggled()),
+            vec![
+                LockChange::new(LockKeys::CapitalLock, LockKeyState::Disabled),
+                LockChange::new(LockKeys::NumberLock, LockKeyState::Enabled),
+                LockChange::new(LockKeys::ScrollingLock, LockKeyState::Enabled),
+            ]
+        );
+    }
+
+    #[test]
+    fn states_bools() {
+        let states = LockKeyStates::from_bools([true, false, true]);
+        assert_eq!(
+            states.iter().collect::<Vec<_>>(),
+            LockKeys::all()
+                .into_iter()
+                .zip([
+                    LockKeyState::Enabled,
+                    LockKeyState::Disabled,
+                    LockKeyState::Enabled
+                ])
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(states.to_bools(), [true, false, true]);
+        for bools in [[false; 3], [true; 3], [false, true, false]] {
+            assert_eq!(LockKeyStates::from_bools(bools).to_bools(), bools);
+        }
+        let mut partial = LockKeyStates::new();
+        partial.insert(LockKeys::NumberLock, LockKeyState::Enabled);
+        partial.insert(LockKeys::ShiftLock, LockKeyState::Enabled);
+        assert_eq!(partial.to_bools(), [false, true, false]);
+    }
+
+    #[test]
+    fn states_eq() {
+        let states: LockKeyStates = [
+            (LockKeys::CapitalLock, LockKeyState::Enabled),
+            (LockKeys::NumberLock, LockKeyState::Disabled),
+        ]
+        .into_iter()
+        .collect();
+        let mut other = LockKeyStates::new();
+        other.insert(LockKeys::NumberLock, LockKeyState::Disabled);
+        other.insert(LockKeys::CapitalLock, LockKeyState::Enabled);
+        assert_eq!(states, other);
+        assert!(states.diff(&other).is_empty());
+    }
+
+    #[test]
+    fn states_diff() {
+        let states: LockKeyStates = [
+            (LockKeys::CapitalLock, LockKeyState::Enabled),
+            (LockKeys::NumberLock, LockKeyState::Disabled),
+            (LockKeys::ScrollingLock, LockKeyState::Disabled),
+        ]
+        .into_iter()
+        .collect();
+        let other: LockKeyStates = [
+            (LockKeys::CapitalLock, LockKeyState::Disabled),
+            (LockKeys::NumberLock, LockKeyState::Disabled),
+            (LockKeys::ScrollingLock, LockKeyState::Enabled),
+        ]
+        .into_iter()
+        .collect();
+        assert_ne!(states, other);
+        assert_eq!(
+            states.diff(&other),
+            vec![
+                (
+                    LockKeys::CapitalLock,
+                    LockKeyState::Enabled,
+                    LockKeyState::Disabled
+                ),
+                (
+                    LockKeys::ScrollingLock,
+                    LockKeyState::Disabled,
+                    LockKeyState::Enabled
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn shift_lock() {
+        let lock_key = LockKey::new();
+        let guard = match lock_key.guard(LockKeys::ShiftLock) {
+            Ok(guard) => guard,
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return,
+            Err(err) => panic!("{}", err),
+        };
+        let old_lock_key_state = guard.state();
+        assert_eq!(
+            lock_key.toggle(LockKeys::ShiftLock).unwrap(),
+            old_lock_key_state
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::ShiftLock).unwrap(),
+            old_lock_key_state.toggle()
+        );
+    }
+
+    #[test]
+    fn states_single_read() {
+        let lock_key = LockKey::new();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        let states = lock_key.states().unwrap();
+        for key in LockKeys::all() {
+            assert_eq!(states.get(key), lock_key.state_opt(key).unwrap());
+        }
+    }
+
+    #[test]
+    fn first_state() {
+        let lock_key = LockKey::new();
+        let first = lock_key.state(LockKeys::CapitalLock).unwrap();
+        assert_eq!(lock_key.state(LockKeys::CapitalLock).unwrap(), first);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn hold() {
+        let lock_key = LockKey::new();
+        let _guard = lock_key.guard(LockKeys::NumberLock).unwrap();
+        let locked = lock_key.state(LockKeys::NumberLock).unwrap();
+        let holder = thread::spawn(|| {
+            LockKey::new()
+                .hold(LockKeys::NumberLock, Duration::from_millis(500))
+                .unwrap()
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            lock_key.latched_state(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        holder.join().unwrap();
+        assert_eq!(
+            lock_key.latched_state(LockKeys::NumberLock).unwrap(),
+            LockKeyState::Disabled
+        );
+        assert_eq!(lock_key.state(LockKeys::NumberLock).unwrap(), locked);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn hold() {
+        let lock_key = LockKey::new();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        lock_key.disable(LockKeys::CapitalLock).unwrap();
+        let state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        let holder = thread::spawn(|| {
+            LockKey::new()
+                .hold(LockKeys::CapitalLock, Duration::from_millis(500))
+                .unwrap()
+        });
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        holder.join().unwrap();
+        assert_eq!(lock_key.state(LockKeys::CapitalLock).unwrap(), state);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn action_behavior() {
+        assert_eq!(
+            linux::action_behavior(Some(linux::XkbSA_LockMods)),
+            LockBehavior::Locking
+        );
+        assert_eq!(
+            linux::action_behavior(Some(linux::XkbSA_LatchMods)),
+            LockBehavior::Latching
+        );
+        assert_eq!(
+            linux::action_behavior(Some(linux::XkbSA_SetMods)),
+            LockBehavior::Momentary
+        );
+        // A group lock action, or no action at all.
+        assert_eq!(linux::action_behavior(Some(0x06)), LockBehavior::Other);
+        assert_eq!(linux::action_behavior(None), LockBehavior::Other);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn lock_behavior() {
+        let lock_key = LockKey::new();
+        assert!(lock_key.lock_behavior(LockKeys::CapitalLock).is_ok());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn named_indicator_state() {
+        // A custom layout mapping both Capital and Number Lock to Mod2, with only Number Lock on.
+        let (locked_mods, mask) = (1 << 4, 1 << 4);
+        assert_eq!(
+            linux::resolve_locked_state(Some(false), locked_mods, mask),
+            LockKeyState::Disabled
+        );
+        assert_eq!(
+            linux::resolve_locked_state(Some(true), locked_mods, mask),
+            LockKeyState::Enabled
+        );
+        // Without the indicator, the mask is used.
+        assert_eq!(
+            linux::resolve_locked_state(None, locked_mods, mask),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            linux::resolve_locked_state(None, 0, mask),
+            LockKeyState::Disabled
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn indicator_bitmask() {
+        // Capital Lock at index 0 and Number Lock at index 1 on, no Scroll Lock indicator.
+        let mut indices = [None; LOCK_KEYS.len()];
+        indices[LockKeys::CapitalLock as usize] = Some(0);
+        indices[LockKeys::NumberLock as usize] = Some(1);
+        let indicators = 0b01;
+        assert_eq!(
+            linux::indicator_on(&indices, LockKeys::CapitalLock, indicators),
+            Some(true)
+        );
+        assert_eq!(
+            linux::indicator_on(&indices, LockKeys::NumberLock, indicators),
+            Some(false)
+        );
+        assert_eq!(
+            linux::indicator_on(&indices, LockKeys::ScrollingLock, indicators),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn latched_state() {
+        let lock_key = LockKey::new();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        lock_key.enable(LockKeys::CapitalLock).unwrap();
+        assert_eq!(
+            lock_key.latched_state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Disabled
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn layout_name() {
+        let lock_key = LockKey::new();
+        assert!(!lock_key.layout_name().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn layout_from_symbols() {
+        let symbols = "pc+us+de:2+inet(evdev)+group(alt_shift_toggle)";
+        assert_eq!(
+            linux::layout_from_symbols(symbols, 0).as_deref(),
+            Some("us")
+        );
+        assert_eq!(
+            linux::layout_from_symbols(symbols, 1).as_deref(),
+            Some("de")
+        );
+        assert_eq!(linux::layout_from_symbols(symbols, 2), None);
+        assert_eq!(
+            linux::layout_from_symbols("pc+us(intl)+inet(evdev)", 0).as_deref(),
+            Some("us")
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    #[ignore = "requires a session without the IOHIDSystem service, e.g. a headless Mac"]
+    fn try_new_without_hid_system() {
+        assert_eq!(
+            LockKey::try_new().unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn x_io_error_exit_handler_lookup() {
+        // The libX11 the tests link against is 1.7 or later, so the symbol is found without a display.
+        assert!(linux::x_set_io_error_exit_handler().is_some());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn x_connection_lost() {
+        // A fake display standing for a connection lost under the lock key object, never passed to Xlib.
+        let mut fake_display = 0u64;
+        let display = &mut fake_display as *mut u64 as *mut linux::Display;
+        assert!(!linux::is_x_display_dead(display));
+        linux::register_x_error_display(display);
+        let lock_key = LockKey {
+            handle: display as *mut _,
+            options: LockKeyBuilder::default(),
+            trace: None,
+            indicators: Default::default(),
+        };
+        assert_eq!(unsafe { linux::x_io_error_handler(display) }, 0);
+        assert!(linux::is_x_display_dead(display));
+        for result in [
+            lock_key.state(LockKeys::CapitalLock),
+            lock_key.set(LockKeys::NumberLock, LockKeyState::Enabled),
+        ] {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+        }
+        drop(lock_key);
+        assert!(!linux::is_x_display_dead(display));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reconnect() {
+        let mut lock_key = LockKey::new();
+        let state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        lock_key.reconnect().unwrap();
+        assert_eq!(lock_key.state(LockKeys::CapitalLock).unwrap(), state);
+        assert_eq!(
+            unsafe { linux::x_io_error_handler(lock_key.handle as *mut _) },
+            0
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        lock_key.reconnect().unwrap();
+        assert_eq!(lock_key.state(LockKeys::CapitalLock).unwrap(), state);
+    }
+
+    #[test]
+    fn same_handle() {
+        let lock_key = LockKey::new();
+        let other = LockKey::new();
+        assert!(lock_key.same_handle(&lock_key));
+        assert_eq!(lock_key.same_handle(&other), cfg!(target_os = "windows"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn config_snapshot() {
+        let lock_key = LockKey::builder()
+            .install_x_error_handler(false)
+            .verify_source(VerifySource::Led)
+            .build()
+            .unwrap();
+        let snapshot = lock_key.config_snapshot();
+        assert_eq!(snapshot.get("backend"), Some("Xlib"));
+        assert!(!snapshot.get("display").unwrap().is_empty());
+        assert_eq!(snapshot.get("install_x_error_handler"), Some("false"));
+        assert_eq!(snapshot.get("verify_source"), Some("Led"));
+        assert_eq!(snapshot.get("trace"), Some("false"));
+        assert!(snapshot.to_string().starts_with("backend: Xlib\n"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn same_display() {
+        let lock_key = LockKey::new();
+        let other = LockKey::new();
+        assert!(!lock_key.same_handle(&other));
+        assert!(lock_key.same_display(&other));
+        assert!(lock_key.same_display(&lock_key));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn without_x_error_handler() {
+        let lock_key = LockKey::builder()
+            .install_x_error_handler(false)
+            .build()
+            .unwrap();
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        assert_eq!(
+            lock_key.enable(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            LockKeyState::Enabled
+        );
+    }
+
+    #[test]
+    fn trace_callback() {
+        let mut lock_key = LockKey::new();
+        let traces = Arc::new(Mutex::new(Vec::new()));
+        let sink = traces.clone();
+        lock_key.set_trace_callback(move |trace| sink.lock().unwrap().push(trace.to_string()));
+        let _guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        lock_key
+            .set(LockKeys::CapitalLock, LockKeyState::Enabled)
+            .unwrap();
+        let traces = traces.lock().unwrap();
+        assert!(!traces.is_empty());
+        assert!(traces.iter().any(|trace| trace.contains("CapitalLock")));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn lightdm_with_numlock() {
+        assert_eq!(
+            linux::lightdm_with_numlock("[LightDM]\nlogind-check-graphical=true\n", LockKeyState::Enabled)
+                .unwrap(),
+            "[LightDM]\nlogind-check-graphical=true\n\n[Seat:*]\ngreeter-setup-script=/usr/bin/numlockx on\n"
+        );
+        assert_eq!(
+            linux::lightdm_with_numlock(
+                "[Seat:*]\ngreeter-session=slick-greeter\n",
+                LockKeyState::Disabled
+            )
+            .unwrap(),
+            "[Seat:*]\ngreeter-setup-script=/usr/bin/numlockx off\ngreeter-session=slick-greeter\n"
+        );
+        assert_eq!(
+            linux::lightdm_with_numlock(
+                "[SeatDefaults]\ngreeter-setup-script = /usr/bin/numlockx on\n",
+                LockKeyState::Disabled
+            )
+            .unwrap(),
+            "[SeatDefaults]\ngreeter-setup-script=/usr/bin/numlockx off\n"
+        );
+        assert_eq!(
+            linux::lightdm_with_numlock(
+                "[Seat:*]\ngreeter-setup-script=/opt/setup.sh\n",
+                LockKeyState::Enabled
+            )
+            .unwrap_err()
+            .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn set_boot_default() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let config =
+            std::env::temp_dir().join(format!("lock_keys-lightdm-{}.conf", std::process::id()));
+        std::fs::write(&config, "[Seat:*]\ngreeter-session=slick-greeter\n").unwrap();
+        std::fs::set_permissions(&config, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let enabled =
+            LockKey::set_boot_default_in(&config, LockKeys::NumberLock, LockKeyState::Enabled);
+        let contents = std::fs::read_to_string(&config).unwrap();
+        // The configuration is replaced as a whole, keeping its permissions and no temporary file.
+        let mode = std::fs::metadata(&config).unwrap().permissions().mode() & 0o777;
+        let mut temp = config.clone().into_os_string();
+        temp.push(".lock_keys.tmp");
+        let temp_left = Path::new(&temp).exists();
+        let unsupported =
+            LockKey::set_boot_default_in(&config, LockKeys::CapitalLock, LockKeyState::Enabled);
+        std::fs::remove_file(&config).unwrap();
+        enabled.unwrap();
+        assert!(contents.contains("greeter-setup-script=/usr/bin/numlockx on\n"));
+        assert_eq!(mode, 0o640);
+        assert!(!temp_left);
+        assert_eq!(unsupported.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            LockKey::set_boot_default_in(&config, LockKeys::NumberLock, LockKeyState::Enabled)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn evdev_led_events() {
+        let events = [
+            evdev::input_event {
+                type_: evdev::EV_LED,
+                code: evdev::LED_CAPSL,
+                value: 1,
+                ..Default::default()
+            },
+            // Synchronization and key events are not LED changes.
+            evdev::input_event::default(),
+            evdev::input_event {
+                type_: 0x01,
+                code: evdev::LED_NUML,
+                value: 1,
+                ..Default::default()
+            },
+            evdev::input_event {
+                type_: evdev::EV_LED,
+                code: evdev::LED_NUML,
+                value: 0,
+                ..Default::default()
+            },
+            // Other LEDs, e.g. compose, are ignored.
+            evdev::input_event {
+                type_: evdev::EV_LED,
+                code: 0x03,
+                value: 1,
+                ..Default::default()
+            },
+        ];
+        let buffer: Vec<u8> = events
+            .iter()
+            .flat_map(|event| unsafe {
+                std::slice::from_raw_parts(
+                    event as *const evdev::input_event as *const u8,
+                    std::mem::size_of::<evdev::input_event>(),
+                )
+                .to_vec()
+            })
+            .collect();
+        assert_eq!(
+            evdev::led_events(&buffer).collect::<Vec<_>>(),
+            vec![
+                (LockKeys::CapitalLock, LockKeyState::Enabled),
+                (LockKeys::NumberLock, LockKeyState::Disabled)
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn evdev_watch_error() {
+        use std::io::Write;
+        use std::os::unix::net::UnixStream;
+
+        // A stream stands for the event device, closing it as when the keyboard is unplugged.
+        let (mut device, mut reader) = UnixStream::pair().unwrap();
+        let (sender, receiver) = mpsc::channel();
+        let handle = watch::spawn_watcher(move |stop, ready, last_changes| {
+            let states: LockKeyStates = [(LockKeys::CapitalLock, LockKeyState::Disabled)]
+                .into_iter()
+                .collect();
+            let mut notifier = watch::ChangeNotifier::new(
+                states,
+                WatchOptions::new(),
+                last_changes,
+                move |key, state| {
+                    let _ = sender.send((key, state));
+                },
+            );
+            let _ = ready.send(Ok(WatchMechanism::EvdevLed));
+            evdev::read_led_events(&mut reader, &mut notifier, states, &stop)
+        })
+        .unwrap();
+        let event = evdev::input_event {
+            type_: evdev::EV_LED,
+            code: evdev::LED_CAPSL,
+            value: 1,
+            ..Default::default()
+        };
+        device
+            .write_all(unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const evdev::input_event as *const u8,
+                    std::mem::size_of::<evdev::input_event>(),
+                )
+            })
+            .unwrap();
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (LockKeys::CapitalLock, LockKeyState::Enabled)
+        );
+        assert!(handle.is_running());
+        drop(device);
+        let start = Instant::now();
+        while handle.is_running() && start.elapsed() < Duration::from_secs(1) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!handle.is_running());
+        assert_eq!(
+            handle.take_error().unwrap().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        assert!(handle.take_error().is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn display_not_set() {
+        // Runs in a child process without `DISPLAY`, as the variable is shared with the other tests.
+        if std::env::var_os("LOCK_KEYS_TEST_NO_DISPLAY").is_some() {
+            let err = LockKey::try_new().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+            assert_eq!(err.to_string(), "DISPLAY not set");
+            return;
+        }
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::display_not_set"])
+            .env_remove("DISPLAY")
+            .env("LOCK_KEYS_TEST_NO_DISPLAY", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn xauthority() {
+        // Runs in a child process without `DISPLAY` and `XAUTHORITY`, as the variables are shared with the other tests.
+        if std::env::var_os("LOCK_KEYS_TEST_XAUTHORITY").is_some() {
+            let path = PathBuf::from("/tmp/lock_keys.Xauthority");
+            // Without a display, the environment is left as it was.
+            let err = LockKey::builder().xauthority(&path).build().unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+            assert_eq!(std::env::var_os("XAUTHORITY"), None);
+            // The child runs no other thread, and no X server listens on this display.
+            std::env::set_var("DISPLAY", ":9999");
+            let err = LockKey::builder().xauthority(&path).build().unwrap_err();
+            assert_eq!(err.to_string(), "XOpenDisplay");
+            assert_eq!(std::env::var_os("XAUTHORITY"), Some(path.clone().into()));
+            // The same file is accepted again, another one is refused instead of rewriting the environment.
+            let err = LockKey::builder().xauthority(&path).build().unwrap_err();
+            assert_eq!(err.to_string(), "XOpenDisplay");
+            let err = LockKey::builder()
+                .xauthority("/tmp/other.Xauthority")
+                .build()
+                .unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+            assert_eq!(std::env::var_os("XAUTHORITY"), Some(path.into()));
+            return;
+        }
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::xauthority"])
+            .env_remove("DISPLAY")
+            .env_remove("XAUTHORITY")
+            .env("LOCK_KEYS_TEST_XAUTHORITY", "1")
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn x_error_handler() {
+        let mut display = 0u8;
+        let display = &mut display as *mut u8 as *mut linux::Display;
+        let mut event = linux::XErrorEvent {
+            type_: 0,
+            display,
+            resourceid: 0,
+            serial: 0,
+            error_code: 10,
+            request_code: 0,
+            minor_code: 0,
+        };
+        linux::register_x_error_display(display);
+        assert_eq!(linux::take_x_error(display), None);
+        unsafe { linux::x_error_handler(display, &mut event) };
+        assert_eq!(linux::take_x_error(display), Some(10));
+        assert_eq!(linux::take_x_error(display), None);
+        linux::unregister_x_error_display(display);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn key_event_delay() {
+        let delay = Duration::from_millis(50);
+        let lock_key = LockKey::builder().key_event_delay(delay).build().unwrap();
+        let guard = lock_key.guard(LockKeys::CapitalLock).unwrap();
+        let now = Instant::now();
+        assert_eq!(
+            lock_key.toggle(LockKeys::CapitalLock).unwrap(),
+            guard.state()
+        );
+        assert!(now.elapsed() >= delay);
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            guard.state().toggle()
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn key_state_toggle_bit() {
+        // Toggled, untoggled, and both while the key is held down (high-order bit set).
+        assert!(windows::is_toggled(0x0001));
+        assert!(!windows::is_toggled(0x0000));
+        assert!(windows::is_toggled(0x8001_u16 as i16));
+        assert!(!windows::is_toggled(0x8000_u16 as i16));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn direct_state_only() {
+        let mut lock_key = LockKey::builder().direct_state_only(true).build().unwrap();
+        let operations = Arc::new(Mutex::new(Vec::new()));
+        let traced = operations.clone();
+        lock_key.set_trace_callback(move |operation| {
+            traced.lock().unwrap().push(operation.to_string())
+        });
+        let state = lock_key.state(LockKeys::NumberLock).unwrap();
+        lock_key.set(LockKeys::NumberLock, !state).unwrap();
+        assert_eq!(lock_key.state(LockKeys::NumberLock).unwrap(), !state);
+        lock_key.set(LockKeys::NumberLock, state).unwrap();
+        assert_eq!(
+            lock_key
+                .hold(LockKeys::NumberLock, Duration::ZERO)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert!(!operations
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|operation| operation.starts_with("SendInput")));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn layout_scan_code() {
+        use winapi::um::winuser::{
+            GetKeyboardLayout, MapVirtualKeyExW, MAPVK_VK_TO_VSC, VK_CAPITAL, VK_NUMLOCK,
+        };
+
+        // The scan codes come from the active layout, which keeps the standard ones for the lock keys.
+        let hkl = unsafe { GetKeyboardLayout(0) };
+        for (vk, standard) in [(VK_CAPITAL, 0x3a), (VK_NUMLOCK, 0x45)] {
+            let expected = unsafe { MapVirtualKeyExW(vk as _, MAPVK_VK_TO_VSC, hkl) };
+            assert_eq!(windows::scan_code(vk), expected);
+            assert_eq!(windows::scan_code(vk), standard);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn secure_desktop_error() {
+        let err = windows::secure_desktop_error(io::Error::from_raw_os_error(5));
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(
+            err.to_string(),
+            "the secure desktop requires the SYSTEM account"
+        );
+        let err = windows::secure_desktop_error(io::Error::from_raw_os_error(2));
+        assert_eq!(err.raw_os_error(), Some(2));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn secure_desktop_unprivileged() {
+        // The tests do not run as SYSTEM, which can reach the secure desktop.
+        let lock_key = LockKey::new();
+        let err = lock_key
+            .set_on_secure_desktop(LockKeys::NumberLock, LockKeyState::Enabled)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn config_snapshot() {
+        let lock_key = LockKey::builder()
+            .key_event_delay(Duration::from_millis(5))
+            .scroll_lock_mode(ScrollLockMode::ReadOnly)
+            .direct_state_only(true)
+            .build()
+            .unwrap();
+        let snapshot = lock_key.config_snapshot();
+        assert_eq!(snapshot.get("backend"), Some("winuser API"));
+        assert_eq!(snapshot.get("key_event_delay"), Some("5ms"));
+        assert_eq!(snapshot.get("scroll_lock_mode"), Some("ReadOnly"));
+        assert_eq!(snapshot.get("direct_state_only"), Some("true"));
+        assert_eq!(snapshot.get("trace"), Some("false"));
+        assert!(snapshot.to_string().starts_with("backend: winuser API\n"));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn retry_blocked() {
+        // Blocked twice, then the session transition is over.
+        let mut attempts = 0;
+        let result = windows::retry_blocked(
+            || {
+                attempts += 1;
+                if attempts <= 2 {
+                    return Err(windows::send_input_error(Some(5)));
+                }
+                Ok(())
+            },
+            5,
+            Duration::from_millis(1),
+        );
+        assert!(result.is_ok());
+        assert_eq!(attempts, 3);
+        let mut attempts = 0;
+        let err = windows::retry_blocked(
+            || {
+                attempts += 1;
+                Err(windows::send_input_error(None))
+            },
+            5,
+            Duration::from_millis(1),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "SendInput was blocked");
+        assert_eq!(attempts, 6);
+        // An invalid parameter is permanent, so it is not retried.
+        let mut attempts = 0;
+        let err = windows::retry_blocked(
+            || {
+                attempts += 1;
+                Err(windows::send_input_error(Some(87)))
+            },
+            5,
+            Duration::from_millis(1),
+        )
+        .unwrap_err();
+        assert!(!windows::is_input_blocked(&err));
+        assert_eq!(err.raw_os_error(), Some(87));
+        assert_eq!(attempts, 1);
+        // A failed key-up tells the key may be left down.
+        let err = windows::key_up_error(LockKeys::CapitalLock, windows::send_input_error(None));
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        assert_eq!(
+            err.to_string(),
+            "CapitalLock may be left held down, its key-up failed: SendInput was blocked"
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn input_idle_wait() {
+        let window = Duration::from_millis(100);
+        // Input 30 ms ago defers the injection for the rest of the window.
+        assert_eq!(
+            windows::input_idle_wait(Duration::from_millis(30), window),
+            Some(Duration::from_millis(70))
+        );
+        assert_eq!(windows::input_idle_wait(window, window), None);
+        assert_eq!(
+            windows::input_idle_wait(Duration::from_secs(1), window),
+            None
+        );
+        assert_eq!(
+            windows::input_idle_wait(Duration::ZERO, Duration::ZERO),
+            None
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn wait_for_input_idle() {
+        let lock_key = LockKey::builder()
+            .wait_for_input_idle(Duration::from_millis(200))
+            .build()
+            .unwrap();
+        let _guard = lock_key.guard(LockKeys::NumberLock).unwrap();
+        // The second toggle runs right after the key events injected by the first one, so it waits for the window.
+        lock_key.toggle(LockKeys::NumberLock).unwrap();
+        let start = Instant::now();
+        lock_key.toggle(LockKeys::NumberLock).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(150));
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn keyboard_states() {
+        let lock_key = LockKey::new();
+        let states = lock_key.states().unwrap();
+        for key in LockKeys::all() {
+            assert_eq!(states.get(key), Some(lock_key.state(key).unwrap()));
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn scroll_lock_presence() {
+        use winapi::um::winuser::VK_SCROLL;
+
+        // Laptops lacking Scrolling Lock have no scan code for it, so `set` refuses to inject the key press.
+        let present = windows::layout_has_vkkey(VK_SCROLL);
+        let lock_key = LockKey::builder()
+            .scroll_lock_mode(ScrollLockMode::Inject)
+            .build()
+            .unwrap();
+        let _guard = lock_key.guard(LockKeys::ScrollingLock).unwrap();
+        match lock_key.toggle(LockKeys::ScrollingLock) {
+            Ok(_) => assert!(present),
+            Err(err) => {
+                assert!(!present);
+                assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn scroll_lock_mode() {
+        // Read-only mode never sends the key press, so `set` only succeeds when the state already matches.
+        let lock_key = LockKey::builder()
+            .scroll_lock_mode(ScrollLockMode::ReadOnly)
+            .build()
+            .unwrap();
+        let state = lock_key.state(LockKeys::ScrollingLock).unwrap();
+        assert_eq!(lock_key.set(LockKeys::ScrollingLock, state).unwrap(), state);
+        assert_eq!(
+            lock_key
+                .set(LockKeys::ScrollingLock, state.toggle())
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+        // The key press is injected by default, see `scroll_lock_presence`.
+        assert_eq!(ScrollLockMode::default(), ScrollLockMode::Inject);
+    }
+
+    #[test]
+    #[cfg(target_os = "windows")]
+    fn post_to_window() {
+        use std::{mem, ptr};
+        use winapi::um::winuser::{
+            CreateWindowExW, DestroyWindow, PeekMessageW, MSG, PM_REMOVE, VK_CAPITAL, WM_KEYDOWN,
+            WM_KEYUP,
+        };
+
+        let class_name: Vec<u16> = "STATIC\0".encode_utf16().collect();
+        let window = unsafe {
+            CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                ptr::null(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        assert!(!window.is_null());
+        let lock_key = LockKey::new();
+        let old_lock_key_state = lock_key.state(LockKeys::CapitalLock).unwrap();
+        lock_key
+            .post_to_window(window, LockKeys::CapitalLock)
+            .unwrap();
+        for message in [WM_KEYDOWN, WM_KEYUP] {
+            let mut msg: MSG = unsafe { mem::zeroed() };
+            assert_ne!(
+                unsafe { PeekMessageW(&mut msg, window, message, message, PM_REMOVE) },
+                0
+            );
+            assert_eq!(msg.wParam, VK_CAPITAL as usize);
+        }
+        assert_eq!(
+            lock_key.state(LockKeys::CapitalLock).unwrap(),
+            old_lock_key_state
+        );
+        unsafe { DestroyWindow(window) };
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn pid_event() {
+        let enabled = macos::pid_event(LockKeys::CapitalLock, LockKeyState::Enabled).unwrap();
+        let disabled = macos::pid_event(LockKeys::CapitalLock, LockKeyState::Disabled).unwrap();
+        assert_eq!(enabled.virtual_key, disabled.virtual_key);
+        assert_ne!(enabled.flags, 0);
+        assert_eq!(disabled.flags, 0);
+        assert_eq!(
+            macos::pid_event(LockKeys::NumberLock, LockKeyState::Enabled)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn new_checked() {
+        let lock_key = LockKey::new_checked().unwrap();
+        lock_key.state(LockKeys::CapitalLock).unwrap();
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    #[ignore = "requires a process without the Input Monitoring permission"]
+    fn new_checked_without_permission() {
+        assert_eq!(
+            LockKey::new_checked().unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+    }
+
+    #[test]
+    #[cfg(not(target_os = "linux"))]
+    fn shift_lock_unsupported() {
+        let mut lock_key = LockKey::new();
+        let traces = Arc::new(AtomicUsize::new(0));
+        let sink = traces.clone();
+        lock_key.set_trace_callback(move |_| {
+            sink.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(
+            lock_key.state(LockKeys::ShiftLock).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            lock_key
+                .set(LockKeys::ShiftLock, LockKeyState::Enabled)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::Unsupported
+        );
+        assert_eq!(
+            lock_key.toggle(LockKeys::ShiftLock).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+        // The key is refused before reaching the backend, which traces every call.
+        assert_eq!(traces.load(Ordering::SeqCst), 0);
     }
 }
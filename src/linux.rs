@@ -1,9 +1,22 @@
-use std::io::{Error, ErrorKind};
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::io::{self, Error, Write};
 use std::mem;
-use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_ulong, c_ushort};
+use std::os::raw::{c_char, c_int, c_long, c_short, c_uchar, c_uint, c_ulong, c_ushort, c_void};
+use std::path::{Path, PathBuf};
 use std::ptr;
+use std::sync::mpsc::TryRecvError;
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+use std::time::Duration;
 
-use crate::{LockKey, LockKeyResult, LockKeyState, LockKeyWrapper, LockKeys};
+use crate::evdev;
+use crate::watch::{self, WatchHandle, WatchMechanism, WatchOptions};
+use crate::{
+    check_supported, unsupported_key, LockKey, LockKeyBuilder, LockKeyResult, LockKeyState,
+    LockKeyStates, LockKeyWrapper, LockKeys, LOCK_KEYS, UNMAPPED_ON_LAYOUT,
+};
 
 #[doc(hidden)]
 #[allow(non_upper_case_globals)]
@@ -17,10 +30,49 @@ pub const XK_Num_Lock: c_uint = 0xff7f;
 #[doc(hidden)]
 #[allow(non_upper_case_globals)]
 pub const XK_Scroll_Lock: c_uint = 0xff14;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XK_Shift_Lock: c_uint = 0xffe6;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XK_Insert: c_uint = 0xff63;
+
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbSymbolsNameMask: c_uint = 1 << 2;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbGroupNamesMask: c_uint = 1 << 12;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbKeySymsMask: c_uint = 1 << 1;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbKeyActionsMask: c_uint = 1 << 4;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbSA_SetMods: c_uchar = 0x01;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbSA_LatchMods: c_uchar = 0x02;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbSA_LockMods: c_uchar = 0x03;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbStateNotify: c_uint = 2;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const XkbModifierLockMask: c_ulong = 1 << 3;
+#[doc(hidden)]
+#[allow(non_upper_case_globals)]
+pub const Success: c_int = 0;
 
 #[doc(hidden)]
 pub enum Display {}
 pub type KeySym = c_ulong;
+#[doc(hidden)]
+pub type Atom = c_ulong;
 
 #[doc(hidden)]
 #[repr(C)]
@@ -44,6 +96,129 @@ pub struct XkbStateRec {
 #[doc(hidden)]
 pub type XkbStatePtr = *mut XkbStateRec;
 
+#[doc(hidden)]
+#[repr(C)]
+pub struct XErrorEvent {
+    pub type_: c_int,
+    pub display: *mut Display,
+    pub resourceid: c_ulong,
+    pub serial: c_ulong,
+    pub error_code: c_uchar,
+    pub request_code: c_uchar,
+    pub minor_code: c_uchar,
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct XEvent {
+    pub pad: [c_long; 24],
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct pollfd {
+    pub fd: c_int,
+    pub events: c_short,
+    pub revents: c_short,
+}
+
+#[doc(hidden)]
+pub const POLLIN: c_short = 0x001;
+
+#[doc(hidden)]
+pub type XErrorHandler = Option<unsafe extern "C" fn(*mut Display, *mut XErrorEvent) -> c_int>;
+
+#[doc(hidden)]
+pub type XIOErrorHandler = Option<unsafe extern "C" fn(*mut Display) -> c_int>;
+
+#[doc(hidden)]
+pub type XIOErrorExitHandler = Option<unsafe extern "C" fn(*mut Display, *mut c_void)>;
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct XkbNamesRec {
+    pub keycodes: Atom,
+    pub geometry: Atom,
+    pub symbols: Atom,
+    pub types: Atom,
+    pub compat: Atom,
+    pub vmods: [Atom; 16],
+    pub indicators: [Atom; 32],
+    pub groups: [Atom; 4],
+    pub keys: *mut c_void,
+    pub key_aliases: *mut c_void,
+    pub radio_groups: *mut Atom,
+    pub phys_symbols: Atom,
+    pub num_keys: c_uchar,
+    pub num_key_aliases: c_uchar,
+    pub num_rg: c_ushort,
+}
+
+#[doc(hidden)]
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct XkbAction {
+    pub type_: c_uchar,
+    pub data: [c_uchar; 7],
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct XkbServerMapRec {
+    pub num_acts: c_ushort,
+    pub size_acts: c_ushort,
+    pub acts: *mut XkbAction,
+    pub behaviors: *mut c_void,
+    pub key_acts: *mut c_ushort,
+    pub explicit: *mut c_uchar,
+    pub vmods: [c_uchar; 16],
+    pub vmodmap: *mut c_ushort,
+}
+
+#[doc(hidden)]
+#[repr(C)]
+pub struct XkbDescRec {
+    pub dpy: *mut Display,
+    pub flags: c_ushort,
+    pub device_spec: c_ushort,
+    pub min_key_code: c_uchar,
+    pub max_key_code: c_uchar,
+    pub ctrls: *mut c_void,
+    pub server: *mut XkbServerMapRec,
+    pub map: *mut c_void,
+    pub indicators: *mut c_void,
+    pub names: *mut XkbNamesRec,
+    pub compat: *mut c_void,
+    pub geom: *mut c_void,
+}
+
+#[doc(hidden)]
+pub type XkbDescPtr = *mut XkbDescRec;
+
+#[link(name = "dl")]
+extern "C" {
+    fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+}
+
+/// The `XSetIOErrorExitHandler` function of Xlib.
+type XSetIOErrorExitHandlerFn = unsafe extern "C" fn(
+    display: *mut Display,
+    handler: XIOErrorExitHandler,
+    user_data: *mut c_void,
+);
+
+static X_SET_IO_ERROR_EXIT_HANDLER: OnceLock<Option<XSetIOErrorExitHandlerFn>> = OnceLock::new();
+
+/// Looks `XSetIOErrorExitHandler` up at runtime, as it only exists since libX11 1.7, or `None` on older ones.
+pub(crate) fn x_set_io_error_exit_handler() -> Option<XSetIOErrorExitHandlerFn> {
+    *X_SET_IO_ERROR_EXIT_HANDLER.get_or_init(|| {
+        // `RTLD_DEFAULT`, i.e. the symbol of the libraries already loaded, libX11 included.
+        let symbol = unsafe { dlsym(ptr::null_mut(), c"XSetIOErrorExitHandler".as_ptr()) };
+        (!symbol.is_null())
+            .then(|| unsafe { mem::transmute::<*mut c_void, XSetIOErrorExitHandlerFn>(symbol) })
+    })
+}
+
 #[link(name = "X11")]
 extern "C" {
     pub fn XOpenDisplay(display_name: *const c_char) -> *mut Display;
@@ -54,52 +229,524 @@ extern "C" {
         affect: c_uint,
         values: c_uint,
     ) -> c_int;
+    pub fn XkbLatchModifiers(
+        display: *mut Display,
+        device_spec: c_uint,
+        affect: c_uint,
+        values: c_uint,
+    ) -> c_int;
     pub fn XkbKeysymToModifiers(dpy: *mut Display, ks: KeySym) -> c_uint;
+    pub fn XkbGetAutoRepeatRate(
+        display: *mut Display,
+        device_spec: c_uint,
+        delay_rtrn: *mut c_uint,
+        interval_rtrn: *mut c_uint,
+    ) -> c_int;
+    pub fn XkbGetNamedIndicator(
+        display: *mut Display,
+        device_spec: c_uint,
+        name: Atom,
+        ndx_rtrn: *mut c_int,
+        state_rtrn: *mut c_int,
+        map_rtrn: *mut c_void,
+        real_rtrn: *mut c_int,
+    ) -> c_int;
+    pub fn XkbGetIndicatorState(
+        display: *mut Display,
+        device_spec: c_uint,
+        state_return: *mut c_uint,
+    ) -> c_int;
+    pub fn XInternAtom(
+        display: *mut Display,
+        atom_name: *const c_char,
+        only_if_exists: c_int,
+    ) -> Atom;
     pub fn XkbGetState(
         display: *mut Display,
         device_spec: c_uint,
         state_return: XkbStatePtr,
     ) -> c_int;
+    pub fn XkbAllocKeyboard() -> XkbDescPtr;
+    pub fn XkbGetMap(display: *mut Display, which: c_uint, device_spec: c_uint) -> XkbDescPtr;
+    pub fn XKeysymToKeycode(display: *mut Display, keysym: KeySym) -> c_uchar;
+    pub fn XkbFreeKeyboard(xkb: XkbDescPtr, which: c_uint, free_all: c_int);
+    pub fn XkbGetNames(display: *mut Display, which: c_uint, xkb: XkbDescPtr) -> c_int;
+    pub fn XGetAtomName(display: *mut Display, atom: Atom) -> *mut c_char;
+    pub fn XFree(data: *mut c_void) -> c_int;
+    pub fn XSetErrorHandler(handler: XErrorHandler) -> XErrorHandler;
+    pub fn XSetIOErrorHandler(handler: XIOErrorHandler) -> XIOErrorHandler;
+    pub fn XSync(display: *mut Display, discard: c_int) -> c_int;
+    pub fn XkbSelectEventDetails(
+        display: *mut Display,
+        device_spec: c_uint,
+        event_type: c_uint,
+        bits_to_change: c_ulong,
+        values_for_bits: c_ulong,
+    ) -> c_int;
+    pub fn XConnectionNumber(display: *mut Display) -> c_int;
+    pub fn XDisplayString(display: *mut Display) -> *mut c_char;
+    pub fn XPending(display: *mut Display) -> c_int;
+    pub fn XNextEvent(display: *mut Display, event_return: *mut XEvent) -> c_int;
+}
+
+extern "C" {
+    pub fn poll(fds: *mut pollfd, nfds: c_ulong, timeout: c_int) -> c_int;
+}
+
+/// How long the XKB event watcher waits for events before checking whether it was stopped, in milliseconds.
+const WATCH_STOP_CHECK_TIMEOUT: c_int = 50;
+
+/// Watches the lock keys waiting for the XKB state notify events, falling back to the LEDs of a keyboard event device
+/// when the display cannot be opened, and to polling when the events cannot be selected.
+pub(crate) fn watch_xkb_state_notify<F>(
+    builder: LockKeyBuilder,
+    options: WatchOptions,
+    f: F,
+) -> io::Result<WatchHandle>
+where
+    F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+{
+    watch::spawn_watcher(move |stop, ready, last_changes| {
+        let lock_key = match LockKey::open(builder) {
+            Ok(lock_key) => lock_key,
+            Err(err) => {
+                // Without X, the LEDs of a readable keyboard event device still tell the changes.
+                if let Some(device) = evdev::open_readable_keyboard() {
+                    return evdev::watch_device_leds(device, options, f, stop, ready, last_changes);
+                }
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let options = if options.debounces_key_repeat() {
+            lock_key
+                .auto_repeat_rate()
+                .map_or(options, |(delay, interval)| {
+                    options.with_key_repeat(delay, interval)
+                })
+        } else {
+            options
+        };
+        let mut notifier = match lock_key.states() {
+            Ok(states) => watch::ChangeNotifier::new(states, options, last_changes, f),
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let display = lock_key.handle as *mut Display;
+        let selected = unsafe {
+            XkbSelectEventDetails(
+                display,
+                XkbUseCoreKbd,
+                XkbStateNotify,
+                XkbModifierLockMask,
+                XkbModifierLockMask,
+            )
+        } != 0;
+        if !selected {
+            let _ = ready.send(Ok(WatchMechanism::Polling));
+            watch::poll_changes(&lock_key, &mut notifier, options, &stop);
+            return Ok(());
+        }
+        let _ = ready.send(Ok(WatchMechanism::XkbStateNotify));
+        let mut fd = pollfd {
+            fd: unsafe { XConnectionNumber(display) },
+            events: POLLIN,
+            revents: 0,
+        };
+        while let Err(TryRecvError::Empty) = stop.try_recv() {
+            // The watcher stops once the connection to the X server was lost.
+            lock_key.check_connected()?;
+            unsafe {
+                if XPending(display) == 0 && poll(&mut fd, 1, WATCH_STOP_CHECK_TIMEOUT) <= 0 {
+                    notifier.flush();
+                    continue;
+                }
+                let mut event: XEvent = mem::zeroed();
+                while XPending(display) > 0 {
+                    XNextEvent(display, &mut event);
+                }
+            }
+            notifier.update(&lock_key);
+        }
+        Ok(())
+    })
+}
+
+/// Displays opened with the crate's X error handler, the errors it received for them and the ones whose connection
+/// was lost.
+struct XErrors {
+    displays: Vec<usize>,
+    errors: Vec<(usize, c_uchar)>,
+    dead: Vec<usize>,
+}
+
+static X_ERRORS: Mutex<XErrors> = Mutex::new(XErrors {
+    displays: Vec::new(),
+    errors: Vec::new(),
+    dead: Vec::new(),
+});
+
+static X_PREVIOUS_ERROR_HANDLER: OnceLock<XErrorHandler> = OnceLock::new();
+
+static X_PREVIOUS_IO_ERROR_HANDLER: OnceLock<XIOErrorHandler> = OnceLock::new();
+
+/// The authority file `XAUTHORITY` was set to by the first lock key object opened with one.
+static XAUTHORITY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Sets `XAUTHORITY` to the authority file the first time only, so the lock key objects opened later (e.g. by the
+/// watcher or timer threads) never write the environment, failing with `InvalidInput` for another file.
+fn set_xauthority(path: &Path) -> io::Result<()> {
+    let current = XAUTHORITY.get_or_init(|| {
+        env::set_var("XAUTHORITY", path);
+        path.to_path_buf()
+    });
+    if current != path {
+        return Err(Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("XAUTHORITY already set to {}", current.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// Records the errors of the displays opened by the crate, forwarding the other ones to the previous handler.
+pub(crate) unsafe extern "C" fn x_error_handler(
+    display: *mut Display,
+    event: *mut XErrorEvent,
+) -> c_int {
+    {
+        let mut x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+        if x_errors.displays.contains(&(display as usize)) {
+            x_errors
+                .errors
+                .push((display as usize, (*event).error_code));
+            return 0;
+        }
+    }
+    match X_PREVIOUS_ERROR_HANDLER.get().copied().flatten() {
+        Some(handler) => handler(display, event),
+        None => 0,
+    }
+}
+
+/// Marks the displays opened by the crate as dead when their connection is lost, forwarding the other ones to the
+/// previous handler.
+pub(crate) unsafe extern "C" fn x_io_error_handler(display: *mut Display) -> c_int {
+    if mark_x_display_dead(display) {
+        return 0;
+    }
+    match X_PREVIOUS_IO_ERROR_HANDLER.get().copied().flatten() {
+        Some(handler) => handler(display),
+        None => 0,
+    }
+}
+
+/// Keeps Xlib from terminating the process once the connection of a display opened by the crate is lost.
+pub(crate) unsafe extern "C" fn x_io_error_exit_handler(display: *mut Display, _: *mut c_void) {
+    mark_x_display_dead(display);
+}
+
+/// Marks the display as dead when it was opened by the crate, returning whether it was.
+fn mark_x_display_dead(display: *mut Display) -> bool {
+    let mut x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+    if !x_errors.displays.contains(&(display as usize)) {
+        return false;
+    }
+    if !x_errors.dead.contains(&(display as usize)) {
+        x_errors.dead.push(display as usize);
+    }
+    true
+}
+
+/// Checks whether the connection of the display was lost.
+pub(crate) fn is_x_display_dead(display: *mut Display) -> bool {
+    let x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+    x_errors.dead.contains(&(display as usize))
+}
+
+pub(crate) fn register_x_error_display(display: *mut Display) {
+    X_PREVIOUS_ERROR_HANDLER.get_or_init(|| unsafe { XSetErrorHandler(Some(x_error_handler)) });
+    X_PREVIOUS_IO_ERROR_HANDLER
+        .get_or_init(|| unsafe { XSetIOErrorHandler(Some(x_io_error_handler)) });
+    let mut x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+    x_errors.displays.push(display as usize);
+}
+
+pub(crate) fn unregister_x_error_display(display: *mut Display) {
+    let mut x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+    x_errors.displays.retain(|&id| id != display as usize);
+    x_errors.errors.retain(|&(id, _)| id != display as usize);
+    x_errors.dead.retain(|&id| id != display as usize);
+}
+
+/// Takes the first error received for the display since the last call.
+pub(crate) fn take_x_error(display: *mut Display) -> Option<c_uchar> {
+    let mut x_errors = X_ERRORS.lock().unwrap_or_else(PoisonError::into_inner);
+    let index = x_errors
+        .errors
+        .iter()
+        .position(|&(id, _)| id == display as usize)?;
+    let (_, error_code) = x_errors.errors.remove(index);
+    x_errors.errors.retain(|&(id, _)| id != display as usize);
+    Some(error_code)
+}
+
+/// What `LockKey::set_and_verify` reads to confirm the lock key changed.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum VerifySource {
+    /// The logical XKB modifier state, as `state` reads it.
+    #[default]
+    Logical,
+    /// The brightness of the keyboard LEDs in the sysfs (`/sys/class/leds/*::capslock`), catching an XKB change the
+    /// LEDs did not follow.
+    Led,
+}
+
+/// Checks that every state read back after setting the lock key is the one it was set to.
+pub(crate) fn check_observed(
+    key: LockKeys,
+    state: LockKeyState,
+    observed: Vec<LockKeyState>,
+) -> LockKeyResult {
+    if let Some(differing) = observed.into_iter().find(|&observed| observed != state) {
+        return Err(Error::other(format!(
+            "{:?} is {} after setting it {}",
+            key, differing, state
+        )));
+    }
+    Ok(state)
+}
+
+/// Symbols components that do not describe a layout, e.g. `inet` in `pc+us+inet(evdev)`.
+const NON_LAYOUT_SYMBOLS: &[&str] = &[
+    "altwin",
+    "capslock",
+    "compose",
+    "ctrl",
+    "eurosign",
+    "group",
+    "inet",
+    "keypad",
+    "kpdl",
+    "level3",
+    "level5",
+    "lv3",
+    "nbsp",
+    "pc",
+    "shift",
+    "srvr_ctrl",
+    "terminate",
+];
+
+/// Extracts the layout of the given group from a XKB symbols name, e.g. `de` from `pc+us+de:2+inet(evdev)` for the group `1`.
+pub(crate) fn layout_from_symbols(symbols: &str, group: usize) -> Option<String> {
+    let mut index = 0;
+    for component in symbols.split('+') {
+        let (component, component_group) = match component.split_once(':') {
+            Some((component, component_group)) => (
+                component,
+                component_group.parse::<usize>().ok()?.checked_sub(1)?,
+            ),
+            None => (component, index),
+        };
+        let name = component.split('(').next().unwrap_or(component);
+        if name.is_empty() || NON_LAYOUT_SYMBOLS.contains(&name) {
+            continue;
+        }
+        if component_group == group {
+            return Some(name.to_string());
+        }
+        index += 1;
+    }
+    None
+}
+
+/// The LightDM configuration file read at boot by the greeter.
+const LIGHTDM_CONFIG: &str = "/etc/lightdm/lightdm.conf";
+
+/// The LightDM seat sections, the legacy `SeatDefaults` one included.
+const LIGHTDM_SEAT_SECTIONS: [&str; 2] = ["[Seat:*]", "[SeatDefaults]"];
+
+/// Replaces the file with the contents through a temporary file in the same directory, keeping its permissions.
+fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock_keys.tmp");
+    let temp = path.with_file_name(name);
+    let result = (|| {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents)?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Sets the `numlockx` greeter setup script to the state in a LightDM configuration, returning the new configuration.
+///
+/// Fails with `InvalidData` when the seat already runs another greeter setup script, to not override it.
+pub(crate) fn lightdm_with_numlock(config: &str, state: LockKeyState) -> io::Result<String> {
+    let script = format!(
+        "greeter-setup-script=/usr/bin/numlockx {}",
+        if state.into() { "on" } else { "off" }
+    );
+    let mut lines: Vec<String> = config.lines().map(str::to_string).collect();
+    let mut seat = None;
+    let mut in_seat = false;
+    for (i, line) in lines.iter_mut().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            in_seat = LIGHTDM_SEAT_SECTIONS.contains(&trimmed);
+            if in_seat && seat.is_none() {
+                seat = Some(i);
+            }
+        } else if in_seat {
+            if let Some(value) = trimmed.strip_prefix("greeter-setup-script") {
+                let value = value.trim_start().trim_start_matches('=').trim();
+                if !value.starts_with("/usr/bin/numlockx") && !value.starts_with("numlockx") {
+                    return Err(Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("greeter-setup-script already set to {:?}", value),
+                    ));
+                }
+                *line = script;
+                return Ok(lines.join("\n") + "\n");
+            }
+        }
+    }
+    match seat {
+        Some(i) => lines.insert(i + 1, script),
+        None => {
+            if lines.last().is_some_and(|line| !line.trim().is_empty()) {
+                lines.push(String::new());
+            }
+            lines.push(LIGHTDM_SEAT_SECTIONS[0].to_string());
+            lines.push(script);
+        }
+    }
+    Ok(lines.join("\n") + "\n")
+}
+
+/// How the XKB keyboard map makes a lock key act, from the action bound to its key, see `LockKey::lock_behavior`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LockBehavior {
+    /// Locks its modifier until the key is pressed again (`LockMods`), the usual lock key behavior.
+    Locking,
+    /// Latches its modifier until the next non-modifier key press (`LatchMods`).
+    Latching,
+    /// Sets its modifier only while the key is held down (`SetMods`), so it does not stick.
+    Momentary,
+    /// Has no modifier action, e.g. the key only sends its keysym.
+    Other,
+}
+
+/// Maps the type of the first XKB action of a key to its lock behavior.
+#[allow(non_upper_case_globals)]
+pub(crate) fn action_behavior(action_type: Option<c_uchar>) -> LockBehavior {
+    match action_type {
+        Some(XkbSA_LockMods) => LockBehavior::Locking,
+        Some(XkbSA_LatchMods) => LockBehavior::Latching,
+        Some(XkbSA_SetMods) => LockBehavior::Momentary,
+        _ => LockBehavior::Other,
+    }
+}
+
+/// Maps a lock key to its keysym.
+fn lock_keysym(key: LockKeys) -> KeySym {
+    (match key {
+        LockKeys::CapitalLock => XK_Caps_Lock,
+        LockKeys::NumberLock => XK_Num_Lock,
+        LockKeys::ScrollingLock => XK_Scroll_Lock,
+        LockKeys::ShiftLock => XK_Shift_Lock,
+        LockKeys::Insert => XK_Insert,
+    }) as KeySym
+}
+
+/// Maps a lock key to the name of its XKB indicator, NUL-terminated.
+fn indicator_name(key: LockKeys) -> Option<&'static [u8]> {
+    match key {
+        LockKeys::CapitalLock => Some(b"Caps Lock\0"),
+        LockKeys::NumberLock => Some(b"Num Lock\0"),
+        LockKeys::ScrollingLock => Some(b"Scroll Lock\0"),
+        LockKeys::ShiftLock => Some(b"Shift Lock\0"),
+        LockKeys::Insert => None,
+    }
+}
+
+/// The indices of the named XKB indicators of the lock keys in the indicator state bitmask, indexed by their
+/// discriminant, `None` for a key whose indicator the keyboard does not have.
+pub(crate) type IndicatorIndices = [Option<c_uint>; LOCK_KEYS.len()];
+
+/// Retrieves whether the named indicator of the lock key is on in the indicator state bitmask, or `None` when the
+/// keyboard has no such indicator.
+pub(crate) fn indicator_on(
+    indices: &IndicatorIndices,
+    key: LockKeys,
+    indicators: c_uint,
+) -> Option<bool> {
+    indices[key as usize].map(|index| indicators & (1 << index) != 0)
+}
+
+/// Resolves a locked lock key state from its named indicator, falling back to its modifier mask in `locked_mods` only
+/// when the indicator is absent, so two keys whose masks collide on a custom layout do not alias.
+pub(crate) fn resolve_locked_state(
+    indicator: Option<bool>,
+    locked_mods: c_uint,
+    mask: c_uint,
+) -> LockKeyState {
+    indicator.unwrap_or(locked_mods & mask != 0).into()
+}
+
+unsafe fn atom_name(display: *mut Display, atom: Atom) -> Option<String> {
+    if atom == 0 {
+        return None;
+    }
+    let name = XGetAtomName(display, atom);
+    if name.is_null() {
+        return None;
+    }
+    let result = CStr::from_ptr(name).to_string_lossy().into_owned();
+    XFree(name as *mut _);
+    Some(result)
 }
 
 #[doc(hidden)]
 #[macro_export]
 macro_rules! xkb_lock_key_mask {
     ($handle:expr,$key:expr) => {
-        XkbKeysymToModifiers(
-            $handle as *mut _,
-            match $key {
-                LockKeys::CapitalLock => XK_Caps_Lock,
-                LockKeys::NumberLock => XK_Num_Lock,
-                LockKeys::ScrollingLock => XK_Scroll_Lock,
-            } as KeySym,
-        )
+        match XkbKeysymToModifiers($handle as *mut _, lock_keysym($key)) {
+            0 => return Err(unsupported_key($key, UNMAPPED_ON_LAYOUT)),
+            mask => mask,
+        }
     };
 }
 
 impl LockKeyWrapper for LockKey {
     /// Creates a new lock key object using [Xlib](https://en.wikipedia.org/wiki/Xlib) for handling.
+    ///
+    /// Panics when the X display cannot be opened, use `try_new` to handle it.
     fn new() -> Self {
-        LockKey {
-            handle: unsafe { XOpenDisplay(ptr::null()) } as *mut _,
-        }
+        Self::try_new().expect("failed to open the X display, use LockKey::try_new() to handle it")
+    }
+
+    /// Tries to create a new lock key object using [Xlib](https://en.wikipedia.org/wiki/Xlib) for handling.
+    fn try_new() -> io::Result<Self> {
+        Self::open(LockKeyBuilder::default())
     }
 
     /// Sets a new state for the lock key using [Xlib](https://en.wikipedia.org/wiki/Xlib).
     fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
-        unsafe {
-            let mask = xkb_lock_key_mask!(self.handle, key);
-            if XkbLockModifiers(
-                self.handle as *mut _,
-                XkbUseCoreKbd,
-                mask,
-                if state.into() { mask } else { 0 },
-            ) != 1
-            {
-                return Err(Error::new(ErrorKind::Other, "XkbLockModifiers"));
-            }
-            Ok(state)
-        }
+        check_supported(key)?;
+        let result = self.xkb_lock_modifiers(key, state);
+        self.trace(
+            format_args!("XkbLockModifiers({:?}, {})", key, state),
+            result,
+        )
     }
 
     /// Enables the lock key using [Xlib](https://en.wikipedia.org/wiki/Xlib).
@@ -114,24 +761,487 @@ impl LockKeyWrapper for LockKey {
 
     /// Toggles the lock key state returning its previous state using [Xlib](https://en.wikipedia.org/wiki/Xlib).
     fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
         let state = self.state(key)?;
         self.set(key, state.toggle())?;
         Ok(state)
     }
 
     /// Retrieves the lock key state using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    ///
+    /// The state comes from the named XKB indicator of the key (e.g. `Caps Lock`), falling back to its modifier mask
+    /// only when the keyboard has no such indicator, so keys sharing a modifier bit on a custom layout do not alias.
+    /// The indicator indices are resolved on the first read.
     fn state(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
+        let result = self.xkb_get_state(key, false);
+        self.trace(format_args!("XkbGetState({:?})", key), result)
+    }
+
+    /// Retrieves the states of the standard lock keys in a single `XkbGetIndicatorState` round trip to the X server using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    ///
+    /// As in `state`, each key is resolved from its named indicator first, all of them read from one indicator state
+    /// bitmask. An `XkbGetState` round trip is only added when a key has no indicator. The indicator indices and the
+    /// modifier masks are resolved on the first call, so polling the states costs one round trip per call instead of
+    /// one per key.
+    fn states(&self) -> io::Result<LockKeyStates> {
+        let result = self.xkb_get_states();
+        self.trace(format_args!("XkbGetIndicatorState()"), result)
+    }
+
+    /// Sets the lock keys to the opposite of their snapshot states at once, in a single `XkbLockModifiers` call using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    fn apply_inverse(&self, snapshot: &LockKeyStates) -> io::Result<()> {
+        let inverse = snapshot.toggled();
+        let result = self.xkb_lock_states(&inverse);
+        self.trace(
+            format_args!("XkbLockModifiers({:?})", inverse.iter().collect::<Vec<_>>()),
+            result,
+        )
+    }
+}
+
+impl LockKey {
+    /// Retrieves the XKB auto-repeat delay and interval of the keyboard, or `None` when they cannot be read.
+    fn auto_repeat_rate(&self) -> Option<(Duration, Duration)> {
+        let (mut delay, mut interval) = (0, 0);
+        let read = unsafe {
+            XkbGetAutoRepeatRate(
+                self.handle as *mut _,
+                XkbUseCoreKbd,
+                &mut delay,
+                &mut interval,
+            )
+        };
+        (read != 0).then(|| {
+            (
+                Duration::from_millis(delay.into()),
+                Duration::from_millis(interval.into()),
+            )
+        })
+    }
+
+    /// Fails with `BrokenPipe` once the connection to the X server was lost, instead of using the dead display.
+    fn check_connected(&self) -> io::Result<()> {
+        if is_x_display_dead(self.handle as *mut _) {
+            return Err(Error::new(
+                io::ErrorKind::BrokenPipe,
+                "X display connection lost",
+            ));
+        }
+        Ok(())
+    }
+
+    fn xkb_lock_modifiers(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        self.check_connected()?;
+        let mask = unsafe { xkb_lock_key_mask!(self.handle, key) };
+        self.xkb_lock_masks(mask, if state.into() { mask } else { 0 })?;
+        Ok(state)
+    }
+
+    fn xkb_lock_states(&self, states: &LockKeyStates) -> io::Result<()> {
+        self.check_connected()?;
+        let (mut affect, mut values) = (0, 0);
+        for (key, state) in states.iter() {
+            let mask = unsafe { xkb_lock_key_mask!(self.handle, key) };
+            affect |= mask;
+            if state.into() {
+                values |= mask;
+            }
+        }
+        self.xkb_lock_masks(affect, values)
+    }
+
+    fn xkb_lock_masks(&self, affect: c_uint, values: c_uint) -> io::Result<()> {
+        unsafe {
+            if XkbLockModifiers(self.handle as *mut _, XkbUseCoreKbd, affect, values) != 1 {
+                return Err(Error::other("XkbLockModifiers"));
+            }
+        }
+        self.check_x_error("XkbLockModifiers")
+    }
+
+    fn xkb_latch_masks(&self, affect: c_uint, values: c_uint) -> io::Result<()> {
+        unsafe {
+            if XkbLatchModifiers(self.handle as *mut _, XkbUseCoreKbd, affect, values) != 1 {
+                return Err(Error::other("XkbLatchModifiers"));
+            }
+        }
+        self.check_x_error("XkbLatchModifiers")
+    }
+
+    /// Waits for the request to be processed, failing with the X error it raised, when the crate's X error handler
+    /// is installed.
+    fn check_x_error(&self, request: &str) -> io::Result<()> {
+        if self.options.install_x_error_handler {
+            unsafe { XSync(self.handle as *mut _, 0) };
+            if let Some(error_code) = take_x_error(self.handle as *mut _) {
+                return Err(Error::other(format!(
+                    "{} failed with X error {}",
+                    request, error_code
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn xkb_get_state(&self, key: LockKeys, latched: bool) -> LockKeyResult {
+        self.check_connected()?;
+        if !latched {
+            // The mask is resolved anyway, so a key the layout does not provide stays unsupported.
+            let mask = self.xkb_mask(key)?;
+            if let Some(index) = self.xkb_indicator_indices()[key as usize] {
+                return Ok((self.xkb_indicator_state()? & (1 << index) != 0).into());
+            }
+            return Ok(resolve_locked_state(None, self.xkb_locked_mods(), mask));
+        }
         unsafe {
             let mask = xkb_lock_key_mask!(self.handle, key);
             let mut state: XkbStateRec = mem::zeroed();
             XkbGetState(self.handle as *mut _, XkbUseCoreKbd, &mut state);
-            Ok(((state.locked_mods as c_uint) & mask != 0).into())
+            Ok(((state.latched_mods as c_uint) & mask != 0).into())
+        }
+    }
+
+    fn xkb_locked_mods(&self) -> c_uint {
+        unsafe {
+            let mut state: XkbStateRec = mem::zeroed();
+            XkbGetState(self.handle as *mut _, XkbUseCoreKbd, &mut state);
+            state.locked_mods as c_uint
+        }
+    }
+
+    /// Resolves the indices of the named XKB indicators of the lock keys once, as the `XInternAtom` and
+    /// `XkbGetNamedIndicator` lookups each cost a round trip.
+    fn xkb_indicator_indices(&self) -> &IndicatorIndices {
+        self.indicators.get_or_init(|| {
+            let mut indices = [None; LOCK_KEYS.len()];
+            for key in LOCK_KEYS {
+                indices[key as usize] = indicator_name(key).and_then(|name| unsafe {
+                    // Only an existing atom can name an indicator, so none is created.
+                    let atom =
+                        XInternAtom(self.handle as *mut _, name.as_ptr() as *const c_char, 1);
+                    if atom == 0 {
+                        return None;
+                    }
+                    let mut index = 0;
+                    let found = XkbGetNamedIndicator(
+                        self.handle as *mut _,
+                        XkbUseCoreKbd,
+                        atom,
+                        &mut index,
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                        ptr::null_mut(),
+                    );
+                    (found != 0 && (0..32).contains(&index)).then_some(index as c_uint)
+                });
+            }
+            indices
+        })
+    }
+
+    /// Reads the state bitmask of all the XKB indicators in a single round trip.
+    fn xkb_indicator_state(&self) -> io::Result<c_uint> {
+        let mut state = 0;
+        if unsafe { XkbGetIndicatorState(self.handle as *mut _, XkbUseCoreKbd, &mut state) }
+            != Success
+        {
+            return Err(Error::other("XkbGetIndicatorState"));
+        }
+        Ok(state)
+    }
+
+    fn xkb_get_states(&self) -> io::Result<LockKeyStates> {
+        self.check_connected()?;
+        let indices = self.xkb_indicator_indices();
+        let indicators = self.xkb_indicator_state()?;
+        // The modifiers are only read for the keys without an indicator.
+        let mut locked_mods = None;
+        let mut states = LockKeyStates::new();
+        for key in LockKeys::all() {
+            match self.xkb_mask(key) {
+                Ok(mask) => {
+                    let indicator = indicator_on(indices, key, indicators);
+                    let locked_mods = match indicator {
+                        Some(_) => 0,
+                        None => *locked_mods.get_or_insert_with(|| self.xkb_locked_mods()),
+                    };
+                    states.insert(key, resolve_locked_state(indicator, locked_mods, mask));
+                }
+                Err(err) if err.kind() == io::ErrorKind::Unsupported => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(states)
+    }
+
+    fn xkb_mask(&self, key: LockKeys) -> io::Result<c_uint> {
+        Ok(unsafe { xkb_lock_key_mask!(self.handle, key) })
+    }
+
+    /// Retrieves the latched state of the lock key modifier using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    ///
+    /// XKB distinguishes locked modifiers, which stay active until the key is pressed again (what `state` reads),
+    /// from latched ones, which stay active only until the next non-modifier key press (e.g. a sticky or temporary
+    /// shift lock). A lock key may be latched without being locked and vice versa.
+    pub fn latched_state(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
+        let result = self.xkb_get_state(key, true);
+        self.trace(format_args!("XkbGetState({:?}, latched)", key), result)
+    }
+
+    /// Sets a new state for the lock key, then confirms it reading it back from the verification source, see
+    /// `LockKeyBuilder::verify_source`.
+    ///
+    /// Fails with `Other` when the read back state differs, and with `NotFound` when verifying the LEDs of a lock
+    /// key the sysfs has none for.
+    pub fn set_and_verify(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        self.set(key, state)?;
+        let observed = match self.options.verify_source {
+            VerifySource::Logical => vec![self.state(key)?],
+            VerifySource::Led => evdev::sysfs_led_states_in(Path::new("/sys"), key)?,
+        };
+        check_observed(key, state, observed)
+    }
+
+    /// Holds the lock key modifier active for the duration, then releases it, emulating a sticky modifier using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    ///
+    /// Unlike `set`, which locks the modifier until it is set again, this latches it (see `latched_state`) as a
+    /// pressed modifier key, and unlatches it once the duration elapsed, leaving its locked state untouched. As any
+    /// latch, a non-modifier key press during the hold releases it earlier.
+    pub fn hold(&self, key: LockKeys, duration: Duration) -> io::Result<()> {
+        check_supported(key)?;
+        self.check_connected()?;
+        let mask = self.xkb_mask(key)?;
+        let result = self.xkb_latch_masks(mask, mask);
+        self.trace(format_args!("XkbLatchModifiers({:?}, latch)", key), result)?;
+        thread::sleep(duration);
+        let result = self.xkb_latch_masks(mask, 0);
+        self.trace(
+            format_args!("XkbLatchModifiers({:?}, unlatch)", key),
+            result,
+        )
+    }
+
+    pub(crate) fn open(options: LockKeyBuilder) -> io::Result<Self> {
+        if env::var_os("DISPLAY")
+            .filter(|display| !display.is_empty())
+            .is_none()
+        {
+            return Err(Error::new(io::ErrorKind::NotConnected, "DISPLAY not set"));
+        }
+        // Only once there is a display to authenticate to, so a failed build leaves the environment as it was.
+        if let Some(xauthority) = &options.xauthority {
+            set_xauthority(xauthority)?;
+        }
+        let handle = unsafe { XOpenDisplay(ptr::null()) };
+        if handle.is_null() {
+            return Err(Error::other("XOpenDisplay"));
+        }
+        if options.install_x_error_handler {
+            register_x_error_display(handle);
+            if let Some(set_exit_handler) = x_set_io_error_exit_handler() {
+                unsafe { set_exit_handler(handle, Some(x_io_error_exit_handler), ptr::null_mut()) };
+            }
+        }
+        // Primes the XKB state with a round trip once the extension is initialized on the display, so the first
+        // `state` does not read it while the initial state is still being delivered.
+        unsafe {
+            let mut state: XkbStateRec = mem::zeroed();
+            XkbGetState(handle, XkbUseCoreKbd, &mut state);
+        }
+        Ok(LockKey {
+            handle: handle as *mut _,
+            options,
+            trace: None,
+            indicators: Default::default(),
+        })
+    }
+
+    /// Retrieves how the keyboard map makes the lock key act, from the XKB action bound to its key, e.g. to tell the
+    /// user why a key configured as momentary does not stick.
+    ///
+    /// Only the action of the first group and level is looked at. Fails with `Unsupported` when the layout does not
+    /// map the key.
+    pub fn lock_behavior(&self, key: LockKeys) -> io::Result<LockBehavior> {
+        check_supported(key)?;
+        self.check_connected()?;
+        let display = self.handle as *mut Display;
+        unsafe {
+            let keycode = XKeysymToKeycode(display, lock_keysym(key));
+            if keycode == 0 {
+                return Err(unsupported_key(key, UNMAPPED_ON_LAYOUT));
+            }
+            let xkb = XkbGetMap(display, XkbKeySymsMask | XkbKeyActionsMask, XkbUseCoreKbd);
+            if xkb.is_null() {
+                return Err(Error::other("XkbGetMap"));
+            }
+            let server = (*xkb).server;
+            let action_type = if server.is_null() || (*server).key_acts.is_null() {
+                None
+            } else {
+                match *(*server).key_acts.add(keycode as usize) {
+                    0 => None,
+                    index => Some((*(*server).acts.add(index as usize)).type_),
+                }
+            };
+            XkbFreeKeyboard(xkb, 0, 1);
+            Ok(action_behavior(action_type))
+        }
+    }
+
+    /// Checks whether both lock key objects are connected to the same X display, comparing the display names they
+    /// were opened with (e.g. `:0`), unlike `same_handle` which compares the connections.
+    pub fn same_display(&self, other: &LockKey) -> bool {
+        self.display_name() == other.display_name()
+    }
+
+    pub(crate) fn display_name(&self) -> Option<String> {
+        unsafe {
+            let name = XDisplayString(self.handle as *mut _);
+            if name.is_null() {
+                return None;
+            }
+            Some(CStr::from_ptr(name).to_string_lossy().into_owned())
+        }
+    }
+
+    /// Reopens the X display once its connection was lost (e.g. the X server restarted), using the options the lock
+    /// key object was built with.
+    ///
+    /// Does nothing while the connection is alive. The connection loss is only detected with the crate's X error
+    /// handler installed, see `LockKeyBuilder::install_x_error_handler`.
+    pub fn reconnect(&mut self) -> io::Result<()> {
+        if !is_x_display_dead(self.handle as *mut _) {
+            return Ok(());
+        }
+        let mut lock_key = Self::open(self.options.clone())?;
+        // The dead display is released along with the new lock key object.
+        mem::swap(&mut self.handle, &mut lock_key.handle);
+        // The indicators of the new server are resolved again.
+        mem::swap(&mut self.indicators, &mut lock_key.indicators);
+        Ok(())
+    }
+
+    /// Sets the state the lock key gets at boot in the LightDM configuration (`/etc/lightdm/lightdm.conf`), using the
+    /// `numlockx` greeter setup script.
+    ///
+    /// Opt-in and requires write access to the configuration, usually root. Only Number Lock is supported, others
+    /// fail with `Unsupported`, and the greeter needs `numlockx` installed. See `set_boot_default_in` for the errors.
+    ///
+    /// Only LightDM is handled. GDM has no greeter setup script, its Number Lock state at boot comes from the
+    /// `org.gnome.desktop.peripherals.keyboard` settings of the `gdm` user, which are left to the dconf tooling.
+    pub fn set_boot_default(key: LockKeys, state: LockKeyState) -> io::Result<()> {
+        Self::set_boot_default_in(Path::new(LIGHTDM_CONFIG), key, state)
+    }
+
+    /// Sets the state the lock key gets at boot in the given LightDM configuration file, as `set_boot_default`.
+    ///
+    /// Fails with `NotFound` when the configuration does not exist, i.e. LightDM is not the display manager, and with
+    /// `InvalidData` when the seat already runs another greeter setup script. The new configuration is written to a
+    /// temporary file in the same directory then renamed over the original, so a failed write leaves it intact.
+    pub fn set_boot_default_in(
+        config: &Path,
+        key: LockKeys,
+        state: LockKeyState,
+    ) -> io::Result<()> {
+        if key != LockKeys::NumberLock {
+            return Err(unsupported_key(key, "numlockx only sets Number Lock"));
+        }
+        let contents = fs::read_to_string(config).map_err(|err| {
+            Error::new(
+                err.kind(),
+                format!(
+                    "cannot read the LightDM configuration {}: {}",
+                    config.display(),
+                    err
+                ),
+            )
+        })?;
+        write_atomically(config, lightdm_with_numlock(&contents, state)?.as_bytes())
+    }
+
+    /// Retrieves the name of the active keyboard layout (e.g. `us`, `de`) using [Xlib](https://en.wikipedia.org/wiki/Xlib).
+    pub fn layout_name(&self) -> io::Result<String> {
+        self.check_connected()?;
+        unsafe {
+            let display = self.handle as *mut Display;
+            let mut state: XkbStateRec = mem::zeroed();
+            if XkbGetState(display, XkbUseCoreKbd, &mut state) != Success {
+                return Err(Error::other("XkbGetState"));
+            }
+            let xkb = XkbAllocKeyboard();
+            if xkb.is_null() {
+                return Err(Error::other("XkbAllocKeyboard"));
+            }
+            let mut name = None;
+            if XkbGetNames(display, XkbSymbolsNameMask | XkbGroupNamesMask, xkb) == Success
+                && !(*xkb).names.is_null()
+            {
+                let names = &*(*xkb).names;
+                let group = state.group as usize;
+                name = atom_name(display, names.symbols)
+                    .and_then(|symbols| layout_from_symbols(&symbols, group))
+                    .or_else(|| {
+                        names
+                            .groups
+                            .get(group)
+                            .and_then(|&atom| atom_name(display, atom))
+                    });
+            }
+            XkbFreeKeyboard(xkb, 0, 1);
+            name.ok_or_else(|| Error::other("XkbGetNames"))
         }
     }
 }
 
+impl LockKeyBuilder {
+    /// Sets whether to install the crate's X error handler, enabled by default.
+    ///
+    /// The handler keeps X errors on the crate's displays from terminating the process and reports them from `set`,
+    /// forwarding errors on other displays to the previously installed handler. As `XSetErrorHandler` is process-wide,
+    /// applications managing their own handler (e.g. GUI toolkits) can disable it, in which case X errors are handled
+    /// by their handler, or by the Xlib default one that terminates the process.
+    ///
+    /// Along with it, a process-wide `XSetIOErrorHandler` handler and a per display `XSetIOErrorExitHandler` one keep
+    /// a lost connection to the X server (e.g. killed or restarted) from terminating the process: the lock key object
+    /// is marked dead and its calls fail with `BrokenPipe`. IO errors on other displays are forwarded to the
+    /// previously installed IO error handler. `XSetIOErrorExitHandler` is looked up at runtime as it only exists since
+    /// libX11 1.7; with an older one, only the IO error handler is installed and Xlib still terminates the process
+    /// once it returns.
+    pub fn install_x_error_handler(mut self, install: bool) -> Self {
+        self.install_x_error_handler = install;
+        self
+    }
+
+    /// Sets what `set_and_verify` reads to confirm the lock key changed, `VerifySource::Logical` by default.
+    pub fn verify_source(mut self, source: VerifySource) -> Self {
+        self.verify_source = source;
+        self
+    }
+
+    /// Sets the X authority file used to authenticate to the display, e.g. from cron or `systemd-run` where
+    /// `XAUTHORITY` is not in the environment.
+    ///
+    /// As Xlib only reads the authority file from the environment, the first lock key object built with it sets
+    /// `XAUTHORITY` for the whole process with `env::set_var` before opening the display, unless `DISPLAY` is not set.
+    /// Writing the environment is unsound while other threads read it (Xlib itself reads it on the watcher and timer
+    /// threads), so that first build must happen before any other thread starts, e.g. at the start of `main`. The
+    /// later builds only check they use the same file, failing with `InvalidInput` otherwise.
+    pub fn xauthority(mut self, path: impl AsRef<Path>) -> Self {
+        self.xauthority = Some(path.as_ref().to_path_buf());
+        self
+    }
+}
+
 impl Drop for LockKey {
     fn drop(&mut self) {
-        unsafe { XCloseDisplay(self.handle as *mut _) };
+        // Closing a dead display would raise the IO error again, so its resources are leaked instead.
+        if !is_x_display_dead(self.handle as *mut _) {
+            unsafe { XCloseDisplay(self.handle as *mut _) };
+        }
+        if self.options.install_x_error_handler {
+            unregister_x_error_display(self.handle as *mut _);
+        }
     }
 }
@@ -1,19 +1,44 @@
 use std::{
-    io::{Error, ErrorKind},
-    os::raw::{c_char, c_int, c_uint},
+    cell::Cell,
+    io::{self, Error, ErrorKind},
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr,
+    sync::mpsc::TryRecvError,
 };
 
-use core_foundation::base::{CFRelease, CFTypeRef};
+use core_foundation::{
+    base::{kCFAllocatorDefault, CFRelease, TCFType},
+    dictionary::CFDictionary,
+    number::CFNumber,
+    runloop::{kCFRunLoopDefaultMode, CFRunLoopGetCurrent, CFRunLoopRunInMode},
+    string::CFString,
+};
 use io_kit_sys::{
-    kIOMasterPortDefault, types::io_connect_t, IOObjectRelease, IOServiceClose,
-    IOServiceGetMatchingService, IOServiceMatching, IOServiceOpen,
+    hid::{
+        base::IOHIDValueRef,
+        element::{IOHIDElementGetUsage, IOHIDElementGetUsagePage},
+        manager::{
+            kIOHIDManagerOptionNone, IOHIDManagerClose, IOHIDManagerCreate, IOHIDManagerOpen,
+            IOHIDManagerRegisterInputValueCallback, IOHIDManagerScheduleWithRunLoop,
+            IOHIDManagerSetDeviceMatching, IOHIDManagerUnscheduleFromRunLoop,
+        },
+        value::IOHIDValueGetElement,
+    },
+    kIOMasterPortDefault,
+    ret::{kIOReturnSuccess, IOReturn},
+    types::io_connect_t,
+    IOObjectRelease, IOServiceClose, IOServiceGetMatchingService, IOServiceMatching, IOServiceOpen,
 };
 use mach::{
     kern_return::{kern_return_t, KERN_SUCCESS},
     traps::mach_task_self,
 };
 
-use crate::{LockKey, LockKeyHandle, LockKeyResult, LockKeyState, LockKeyWrapper, LockKeys};
+use crate::watch::{self, WatchHandle, WatchMechanism, WatchOptions};
+use crate::{
+    check_supported, unsupported_key, LockKey, LockKeyBuilder, LockKeyHandle, LockKeyResult,
+    LockKeyState, LockKeyWrapper, LockKeys,
+};
 
 #[allow(non_upper_case_globals)]
 const kIOHIDSystemClass: &[u8; 12] = b"IOHIDSystem\0";
@@ -23,6 +48,29 @@ const kIOHIDParamConnectType: c_uint = 1;
 const kIOHIDCapsLockState: c_int = 0x00000001;
 #[allow(non_upper_case_globals)]
 const kIOHIDNumLockState: c_int = 0x00000002;
+#[allow(non_upper_case_globals)]
+const kIOReturnNotPrivileged: kern_return_t = 0xe00002c1_u32 as kern_return_t;
+#[allow(non_upper_case_globals)]
+const kIOReturnNotPermitted: kern_return_t = 0xe00002e2_u32 as kern_return_t;
+
+#[allow(non_upper_case_globals)]
+const kVK_CapsLock: u16 = 0x39;
+#[allow(non_upper_case_globals)]
+const kCGEventFlagsChanged: u32 = 12;
+#[allow(non_upper_case_globals)]
+const kCGEventFlagMaskAlphaShift: u64 = 0x00010000;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventCreateKeyboardEvent(
+        source: *const c_void,
+        virtual_key: u16,
+        key_down: bool,
+    ) -> *mut c_void;
+    fn CGEventSetType(event: *mut c_void, event_type: u32);
+    fn CGEventSetFlags(event: *mut c_void, flags: u64);
+    fn CGEventPostToPid(pid: c_int, event: *mut c_void);
+}
 
 extern "C" {
     #[doc(hidden)]
@@ -43,7 +91,7 @@ extern "C" {
 #[macro_export]
 macro_rules! io_kit_raise_error {
     ($ident:expr) => {
-        Error::new(ErrorKind::Other, $ident)
+        Error::other($ident)
     };
 }
 
@@ -59,32 +107,26 @@ macro_rules! io_kit_check_modifier_lock_state {
 
 impl LockKeyWrapper for LockKey {
     /// Creates a new lock key object using [IOKit](https://developer.apple.com/documentation/iokit) for handling.
+    ///
+    /// Panics when the `IOHIDSystem` service is not available, use `try_new` in headless contexts (e.g. SSH or CI).
     fn new() -> Self {
-        let mut ioc: io_connect_t = 0;
-        unsafe {
-            let mdict = IOServiceMatching(kIOHIDSystemClass.as_ptr() as *const c_char);
-            let ios = IOServiceGetMatchingService(kIOMasterPortDefault, mdict);
-            if ios == 0 && !mdict.is_null() {
-                CFRelease(mdict as CFTypeRef);
-                panic!("IOServiceGetMatchingService() failed");
-            }
-            let kr = IOServiceOpen(ios, mach_task_self(), kIOHIDParamConnectType, &mut ioc);
-            IOObjectRelease(ios);
-            if kr != KERN_SUCCESS {
-                panic!("IOServiceOpen() failed");
-            }
-        }
-        LockKey {
-            handle: ioc as *mut LockKeyHandle,
-        }
+        Self::try_new()
+            .expect("failed to open the IOHIDSystem service, use LockKey::try_new() to handle it")
+    }
+
+    /// Tries to create a new lock key object using [IOKit](https://developer.apple.com/documentation/iokit) for handling.
+    fn try_new() -> io::Result<Self> {
+        Self::open(LockKeyBuilder::default())
     }
 
     /// Sets a new state for the lock key using [IOKit](https://developer.apple.com/documentation/iokit).
     fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
-        io_kit_check_modifier_lock_state!(unsafe {
-            IOHIDSetModifierLockState(self.handle as io_connect_t, key.into(), state.into())
-        });
-        Ok(state)
+        check_supported(key)?;
+        let result = self.io_hid_set_modifier_lock_state(key, state);
+        self.trace(
+            format_args!("IOHIDSetModifierLockState({:?}, {})", key, state),
+            result,
+        )
     }
 
     /// Enables the lock key using [IOKit](https://developer.apple.com/documentation/iokit).
@@ -99,6 +141,7 @@ impl LockKeyWrapper for LockKey {
 
     /// Toggles the lock key state returning its previous state using [IOKit](https://developer.apple.com/documentation/iokit).
     fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
         let state = self.state(key)?;
         self.set(key, state.toggle())?;
         Ok(state)
@@ -106,20 +149,132 @@ impl LockKeyWrapper for LockKey {
 
     /// Retrieves the lock key state using [IOKit](https://developer.apple.com/documentation/iokit).
     fn state(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
+        let result = self.io_hid_get_modifier_lock_state(key);
+        self.trace(format_args!("IOHIDGetModifierLockState({:?})", key), result)
+    }
+}
+
+impl LockKey {
+    fn io_hid_set_modifier_lock_state(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        io_kit_check_modifier_lock_state!(unsafe {
+            IOHIDSetModifierLockState(self.handle as io_connect_t, key.try_into()?, state.into())
+        });
+        Ok(state)
+    }
+
+    fn io_hid_get_modifier_lock_state(&self, key: LockKeys) -> LockKeyResult {
         let state: bool = false;
         io_kit_check_modifier_lock_state!(unsafe {
-            IOHIDGetModifierLockState(self.handle as io_connect_t, key.into(), &state)
+            IOHIDGetModifierLockState(self.handle as io_connect_t, key.try_into()?, &state)
         });
         Ok(state.into())
     }
+
+    pub(crate) fn open(options: LockKeyBuilder) -> io::Result<Self> {
+        let mut ioc: io_connect_t = 0;
+        unsafe {
+            // `IOServiceGetMatchingService` consumes the matching dictionary, even on failure.
+            let mdict = IOServiceMatching(kIOHIDSystemClass.as_ptr() as *const c_char);
+            let ios = IOServiceGetMatchingService(kIOMasterPortDefault, mdict);
+            if ios == 0 {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    "IOServiceGetMatchingService",
+                ));
+            }
+            let kr = IOServiceOpen(ios, mach_task_self(), kIOHIDParamConnectType, &mut ioc);
+            IOObjectRelease(ios);
+            if kr != KERN_SUCCESS {
+                return Err(io_kit_raise_error!("IOServiceOpen"));
+            }
+        }
+        Ok(LockKey {
+            handle: ioc as *mut LockKeyHandle,
+            options,
+            trace: None,
+        })
+    }
+
+    /// Creates a new lock key object checking upfront that the process is allowed to read the lock key states using [IOKit](https://developer.apple.com/documentation/iokit).
+    ///
+    /// Fails with `PermissionDenied` when the process lacks the Accessibility/Input Monitoring permission, instead of
+    /// failing on the first `set`.
+    pub fn new_checked() -> io::Result<LockKey> {
+        let lock_key = Self::try_new()?;
+        let state: bool = false;
+        match unsafe {
+            IOHIDGetModifierLockState(lock_key.handle as io_connect_t, kIOHIDCapsLockState, &state)
+        } {
+            KERN_SUCCESS => Ok(lock_key),
+            kr if kr == kIOReturnNotPrivileged || kr == kIOReturnNotPermitted => Err(Error::new(
+                ErrorKind::PermissionDenied,
+                "IOHIDModifierLockState",
+            )),
+            _ => Err(io_kit_raise_error!("IOHIDModifierLockState")),
+        }
+    }
 }
 
-impl From<LockKeys> for c_int {
-    fn from(val: LockKeys) -> Self {
+/// A keyboard event posted to a process, as its virtual key, event type and flags.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct PidEvent {
+    pub(crate) virtual_key: u16,
+    pub(crate) event_type: u32,
+    pub(crate) flags: u64,
+}
+
+/// Describes the event telling a process the lock key changed to the state, only Capital Lock has one on macOS.
+pub(crate) fn pid_event(key: LockKeys, state: LockKeyState) -> io::Result<PidEvent> {
+    match key {
+        LockKeys::CapitalLock => Ok(PidEvent {
+            virtual_key: kVK_CapsLock,
+            event_type: kCGEventFlagsChanged,
+            flags: if state.into() {
+                kCGEventFlagMaskAlphaShift
+            } else {
+                0
+            },
+        }),
+        _ => Err(unsupported_key(key, "no process event on macOS")),
+    }
+}
+
+impl LockKey {
+    /// Posts the lock key change to the process using [Quartz Event Services](https://developer.apple.com/documentation/coregraphics/quartz_event_services).
+    ///
+    /// Only the process sees the key state change, e.g. a backgrounded app being automated, the global state and
+    /// the keyboard LED stay as they are. Only Capital Lock is supported, and the process needs the Accessibility
+    /// permission to post events.
+    pub fn set_for_pid(&self, pid: i32, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        let event = pid_event(key, state)?;
+        unsafe {
+            let cg_event = CGEventCreateKeyboardEvent(ptr::null(), event.virtual_key, state.into());
+            if cg_event.is_null() {
+                return Err(io_kit_raise_error!("CGEventCreateKeyboardEvent"));
+            }
+            CGEventSetType(cg_event, event.event_type);
+            CGEventSetFlags(cg_event, event.flags);
+            CGEventPostToPid(pid, cg_event);
+            CFRelease(cg_event as *const c_void);
+        }
+        self.trace(
+            format_args!("CGEventPostToPid({}, {:?}, {})", pid, key, state),
+            Ok(state),
+        )
+    }
+}
+
+impl TryFrom<LockKeys> for c_int {
+    type Error = Error;
+
+    fn try_from(val: LockKeys) -> Result<Self, Self::Error> {
         match val {
-            LockKeys::CapitalLock => kIOHIDCapsLockState,
-            LockKeys::NumberLock => kIOHIDNumLockState,
-            LockKeys::ScrollingLock => todo!(),
+            LockKeys::CapitalLock => Ok(kIOHIDCapsLockState),
+            LockKeys::NumberLock => Ok(kIOHIDNumLockState),
+            LockKeys::ScrollingLock | LockKeys::ShiftLock | LockKeys::Insert => {
+                Err(unsupported_key(val, "not exposed by IOKit"))
+            }
         }
     }
 }
@@ -129,3 +284,113 @@ impl Drop for LockKey {
         unsafe { IOServiceClose(self.handle as io_connect_t) };
     }
 }
+
+/// The HID usage page of the keyboard keys.
+const KEYBOARD_USAGE_PAGE: u32 = 0x07;
+
+/// The HID usages of the Capital Lock and keypad Number Lock keys.
+const LOCK_KEY_USAGES: [u32; 2] = [0x39, 0x53];
+
+/// How long the HID watcher runs its run loop before checking whether it was stopped, in seconds.
+const WATCH_STOP_CHECK_TIMEOUT: f64 = 0.05;
+
+/// Records the lock key events for the watcher owning the flag passed as context.
+unsafe extern "C" fn lock_key_value_callback(
+    context: *mut c_void,
+    _result: IOReturn,
+    _sender: *mut c_void,
+    value: IOHIDValueRef,
+) {
+    let element = IOHIDValueGetElement(value);
+    if IOHIDElementGetUsagePage(element) == KEYBOARD_USAGE_PAGE
+        && LOCK_KEY_USAGES.contains(&IOHIDElementGetUsage(element))
+    {
+        (*(context as *const Cell<bool>)).set(true);
+    }
+}
+
+/// Watches the lock keys with an `IOHIDManager` input value callback on the keyboards, falling back to polling when
+/// the manager cannot be opened, e.g. without the Input Monitoring permission.
+pub(crate) fn watch_io_hid_values<F>(
+    builder: LockKeyBuilder,
+    options: WatchOptions,
+    f: F,
+) -> io::Result<WatchHandle>
+where
+    F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+{
+    watch::spawn_watcher(move |stop, ready, last_changes| {
+        let lock_key = match LockKey::open(builder) {
+            Ok(lock_key) => lock_key,
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let mut notifier = match lock_key.states() {
+            Ok(states) => watch::ChangeNotifier::new(states, options, last_changes, f),
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let event = Cell::new(false);
+        let manager = unsafe { IOHIDManagerCreate(kCFAllocatorDefault, kIOHIDManagerOptionNone) };
+        if manager.is_null() {
+            let _ = ready.send(Ok(WatchMechanism::Polling));
+            watch::poll_changes(&lock_key, &mut notifier, options, &stop);
+            return Ok(());
+        }
+        let matching = CFDictionary::from_CFType_pairs(&[
+            (
+                CFString::from_static_string("DeviceUsagePage"),
+                CFNumber::from(1),
+            ),
+            (
+                CFString::from_static_string("DeviceUsage"),
+                CFNumber::from(6),
+            ),
+        ]);
+        let opened = unsafe {
+            IOHIDManagerSetDeviceMatching(manager, matching.as_concrete_TypeRef());
+            IOHIDManagerRegisterInputValueCallback(
+                manager,
+                lock_key_value_callback,
+                &event as *const Cell<bool> as *mut c_void,
+            );
+            IOHIDManagerScheduleWithRunLoop(manager, CFRunLoopGetCurrent(), kCFRunLoopDefaultMode);
+            IOHIDManagerOpen(manager, kIOHIDManagerOptionNone) == kIOReturnSuccess
+        };
+        if opened {
+            let _ = ready.send(Ok(WatchMechanism::IoHidValue));
+            // The callback runs before the system toggles the key, so the states are read on the next wake.
+            let mut pending = false;
+            while let Err(TryRecvError::Empty) = stop.try_recv() {
+                unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, WATCH_STOP_CHECK_TIMEOUT, 1) };
+                if pending {
+                    notifier.update(&lock_key);
+                } else {
+                    notifier.flush();
+                }
+                pending = event.replace(false);
+            }
+        } else {
+            let _ = ready.send(Ok(WatchMechanism::Polling));
+        }
+        unsafe {
+            IOHIDManagerUnscheduleFromRunLoop(
+                manager,
+                CFRunLoopGetCurrent(),
+                kCFRunLoopDefaultMode,
+            );
+            if opened {
+                IOHIDManagerClose(manager, kIOHIDManagerOptionNone);
+            }
+            CFRelease(manager as *const c_void);
+        }
+        if !opened {
+            watch::poll_changes(&lock_key, &mut notifier, options, &stop);
+        }
+        Ok(())
+    })
+}
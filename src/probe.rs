@@ -0,0 +1,117 @@
+use std::fmt;
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+use crate::{LockKeyResult, LockKeyWrapper, LockKeys};
+
+/// A report of the lock key states and whether they are supported, e.g. the one retrieved by
+/// `LockKeyWrapper::probe()`.
+///
+/// Its `Display` renders an aligned table for diagnostics:
+///
+/// ```text
+/// KEY            STATE     CAPABILITY
+/// CapitalLock    enabled   supported
+/// NumberLock     disabled  supported
+/// ScrollingLock  -         unsupported
+/// ```
+#[derive(Debug)]
+pub struct ProbeReport {
+    entries: Vec<(LockKeys, LockKeyResult)>,
+}
+
+impl ProbeReport {
+    /// Iterates over the probed lock keys and the results of reading their states.
+    pub fn iter(&self) -> impl Iterator<Item = (LockKeys, &LockKeyResult)> + '_ {
+        self.entries.iter().map(|(key, result)| (*key, result))
+    }
+
+    /// Checks whether the lock key was probed and is supported.
+    pub fn is_supported(&self, key: LockKeys) -> bool {
+        self.iter()
+            .any(|(probed, result)| probed == key && !is_unsupported(result))
+    }
+}
+
+impl FromIterator<(LockKeys, LockKeyResult)> for ProbeReport {
+    fn from_iter<I: IntoIterator<Item = (LockKeys, LockKeyResult)>>(iter: I) -> Self {
+        ProbeReport {
+            entries: iter.into_iter().collect(),
+        }
+    }
+}
+
+fn is_unsupported(result: &LockKeyResult) -> bool {
+    matches!(result, Err(err) if err.kind() == io::ErrorKind::Unsupported)
+}
+
+impl fmt::Display for ProbeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows: Vec<[String; 3]> = self
+            .iter()
+            .map(|(key, result)| {
+                let (state, capability) = match result {
+                    Ok(state) => (state.to_string(), "supported"),
+                    Err(_) if is_unsupported(result) => ("-".to_string(), "unsupported"),
+                    Err(err) => (format!("error: {}", err), "supported"),
+                };
+                [format!("{:?}", key), state, capability.to_string()]
+            })
+            .collect();
+        let header = ["KEY", "STATE", "CAPABILITY"];
+        let mut widths = header.map(str::len);
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let header = header.map(str::to_string);
+        for row in std::iter::once(&header).chain(&rows) {
+            writeln!(
+                f,
+                "{:key$}  {:state$}  {}",
+                row[0],
+                row[1],
+                row[2],
+                key = widths[0],
+                state = widths[1]
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// An endless iterator of fresh probe reports, one per interval, e.g. the one returned by
+/// `LockKeyWrapper::probe_stream()`.
+///
+/// The first report is produced right away, each following one after sleeping for the interval in `next`, so the
+/// probing stops as soon as the iterator is dropped.
+#[derive(Debug)]
+pub struct ProbeStream<'a, T: ?Sized> {
+    lock_key: &'a T,
+    interval: Duration,
+    started: bool,
+}
+
+impl<'a, T: ?Sized> ProbeStream<'a, T> {
+    pub(crate) fn new(lock_key: &'a T, interval: Duration) -> Self {
+        ProbeStream {
+            lock_key,
+            interval,
+            started: false,
+        }
+    }
+}
+
+impl<T: LockKeyWrapper + ?Sized> Iterator for ProbeStream<'_, T> {
+    type Item = ProbeReport;
+
+    fn next(&mut self) -> Option<ProbeReport> {
+        if self.started {
+            thread::sleep(self.interval);
+        }
+        self.started = true;
+        Some(self.lock_key.probe())
+    }
+}
@@ -0,0 +1,43 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{LockKeyStates, LockKeys};
+
+/// A keyboard profile telling which standard lock keys are enabled, e.g. stored in a TOML file as:
+///
+/// ```toml
+/// capital_lock = false
+/// num_lock = true
+/// scroll_lock = false
+/// ```
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct LockKeyProfile {
+    pub capital_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl LockKeyProfile {
+    /// Loads the profile from a TOML file, failing with `InvalidData` when it cannot be parsed.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        toml::from_str(&contents).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Returns the profile as a snapshot of the lock key states, leaving out the keys unsupported on the current
+    /// platform.
+    pub fn states(&self) -> LockKeyStates {
+        [
+            (LockKeys::CapitalLock, self.capital_lock),
+            (LockKeys::NumberLock, self.num_lock),
+            (LockKeys::ScrollingLock, self.scroll_lock),
+        ]
+        .into_iter()
+        .filter(|(key, _)| key.is_supported())
+        .map(|(key, enabled)| (key, enabled.into()))
+        .collect()
+    }
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{LockKey, LockKeyState, LockKeyWrapper, LockKeys};
+
+/// A handle to the timer restoring a lock key enabled by `LockKey::enable_for`.
+///
+/// Dropping the handle leaves the timer running, use `cancel` to keep the lock key enabled.
+#[derive(Debug)]
+pub struct TimerHandle {
+    cancel: Sender<()>,
+    thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer, leaving the lock key as it is, unless it was already restored.
+    pub fn cancel(mut self) {
+        let _ = self.cancel.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+
+    /// Waits for the timer to restore the lock key, failing with the error of the restore, e.g. when the OS handle
+    /// could not be opened again.
+    pub fn wait(mut self) -> io::Result<()> {
+        match self.thread.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("lock key timer thread panicked"))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Spawns a thread calling `restore` once the duration has elapsed, unless the returned handle is cancelled first.
+pub(crate) fn schedule<F>(duration: Duration, restore: F) -> io::Result<TimerHandle>
+where
+    F: FnOnce() -> io::Result<()> + Send + 'static,
+{
+    let (cancel, cancelled) = mpsc::channel();
+    let deadline = Instant::now() + duration;
+    let thread = thread::Builder::new()
+        .name("lock_keys-timer".to_string())
+        .spawn(move || {
+            match cancelled.recv_timeout(duration) {
+                Ok(()) => return Ok(()),
+                Err(RecvTimeoutError::Timeout) => {}
+                // The handle was dropped, the timer runs out anyway.
+                Err(RecvTimeoutError::Disconnected) => {
+                    thread::sleep(deadline.saturating_duration_since(Instant::now()))
+                }
+            }
+            restore()
+        })?;
+    Ok(TimerHandle {
+        cancel,
+        thread: Some(thread),
+    })
+}
+
+/// Enables the lock key for the duration, then restores its previous state with the lock key object returned by
+/// `open` from the timer thread.
+pub(crate) fn enable_for<T, U, F>(
+    lock_key: &T,
+    key: LockKeys,
+    duration: Duration,
+    open: F,
+) -> io::Result<TimerHandle>
+where
+    T: LockKeyWrapper,
+    U: LockKeyWrapper,
+    F: FnOnce() -> io::Result<U> + Send + 'static,
+{
+    let previous = lock_key.state(key)?;
+    lock_key.set_known(key, previous, LockKeyState::Enabled)?;
+    schedule(duration, move || open()?.set(key, previous).map(|_| ()))
+}
+
+impl LockKey {
+    /// Enables the lock key for the duration, then restores its previous state from a background thread, e.g. Capital
+    /// Lock on for 5 seconds as an accessibility cue.
+    ///
+    /// The timer opens its own OS handle using the options of this lock key object when restoring, its errors being
+    /// returned by `TimerHandle::wait`. A change made by others during the window is overwritten by the restore.
+    pub fn enable_for(&self, key: LockKeys, duration: Duration) -> io::Result<TimerHandle> {
+        let options = self.options.clone();
+        enable_for(self, key, duration, move || options.build())
+    }
+}
@@ -0,0 +1,138 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+
+use crate::{
+    LockChange, LockKey, LockKeyResult, LockKeyState, LockKeyStates, LockKeyWrapper, LockKeys,
+};
+
+/// How many changes `LockKeyUndo::new` keeps by default.
+const DEFAULT_CAPACITY: usize = 32;
+
+/// A lock key object recording the previous state of the lock keys it changes, so `undo` can restore them.
+///
+/// Every `set`/`toggle` that changes a lock key pushes its previous state onto a bounded stack, the oldest changes
+/// being dropped once it is full. Changes made by others (e.g. the user pressing the key) are not recorded.
+#[derive(Debug)]
+pub struct LockKeyUndo<T = LockKey> {
+    lock_key: T,
+    capacity: usize,
+    changes: RefCell<VecDeque<LockChange>>,
+}
+
+impl<T: LockKeyWrapper> LockKeyUndo<T> {
+    /// Wraps the lock key object, keeping up to 32 changes.
+    pub fn new(lock_key: T) -> Self {
+        Self::with_capacity(lock_key, DEFAULT_CAPACITY)
+    }
+
+    /// Wraps the lock key object, keeping up to `capacity` changes.
+    pub fn with_capacity(lock_key: T, capacity: usize) -> Self {
+        LockKeyUndo {
+            lock_key,
+            capacity,
+            changes: RefCell::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Retrieves the wrapped lock key object.
+    pub fn get_ref(&self) -> &T {
+        &self.lock_key
+    }
+
+    /// Retrieves how many changes can be undone.
+    pub fn len(&self) -> usize {
+        self.changes.borrow().len()
+    }
+
+    /// Checks whether there is no change to undo.
+    pub fn is_empty(&self) -> bool {
+        self.changes.borrow().is_empty()
+    }
+
+    /// Restores the lock key changed last to its previous state, returning it, or `None` when there is nothing to
+    /// undo.
+    ///
+    /// The change stays on the stack when restoring it fails, so it can be retried.
+    pub fn undo(&self) -> io::Result<Option<LockKeys>> {
+        let Some(change) = self.changes.borrow_mut().pop_back() else {
+            return Ok(None);
+        };
+        if let Err(err) = self.lock_key.set(change.key, change.state) {
+            self.changes.borrow_mut().push_back(change);
+            return Err(err);
+        }
+        Ok(Some(change.key))
+    }
+
+    /// Records the previous state of a changed lock key, dropping the oldest change when the stack is full.
+    fn record(&self, key: LockKeys, previous: LockKeyState) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut changes = self.changes.borrow_mut();
+        if changes.len() == self.capacity {
+            changes.pop_front();
+        }
+        changes.push_back(LockChange::new(key, previous));
+    }
+}
+
+impl<T: LockKeyWrapper> LockKeyWrapper for LockKeyUndo<T> {
+    /// Creates a new lock key object recording its changes, wrapping `T::new()`.
+    fn new() -> Self {
+        Self::new(T::new())
+    }
+
+    /// Tries to create a new lock key object recording its changes, wrapping `T::try_new()`.
+    fn try_new() -> io::Result<Self> {
+        Ok(Self::new(T::try_new()?))
+    }
+
+    /// Sets a new state for the lock key, recording its previous state when it changes.
+    fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        let previous = self.lock_key.state(key)?;
+        self.set_known(key, previous, state)
+    }
+
+    /// Enables the lock key, recording its previous state when it changes.
+    fn enable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Enabled)
+    }
+
+    /// Disables the lock key, recording its previous state when it changes.
+    fn disable(&self, key: LockKeys) -> LockKeyResult {
+        self.set(key, LockKeyState::Disabled)
+    }
+
+    /// Toggles the lock key state returning its previous state, which is recorded.
+    fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        let previous = self.lock_key.toggle(key)?;
+        self.record(key, previous);
+        Ok(previous)
+    }
+
+    /// Retrieves the lock key state.
+    fn state(&self, key: LockKeys) -> LockKeyResult {
+        self.lock_key.state(key)
+    }
+
+    /// Retrieves the states of the standard lock keys.
+    fn states(&self) -> io::Result<LockKeyStates> {
+        self.lock_key.states()
+    }
+
+    /// Sets a new state for the lock key trusting `current` as its state, recording it when it changes.
+    fn set_known(
+        &self,
+        key: LockKeys,
+        current: LockKeyState,
+        desired: LockKeyState,
+    ) -> LockKeyResult {
+        let state = self.lock_key.set_known(key, current, desired)?;
+        if current != desired {
+            self.record(key, current);
+        }
+        Ok(state)
+    }
+}
@@ -0,0 +1,355 @@
+use std::io;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{LockKey, LockKeyState, LockKeyStates, LockKeyWrapper, LockKeys, LOCK_KEYS};
+
+/// The time of the last change reported by a watcher for each lock key, indexed by its discriminant.
+pub(crate) type LastChanges = Arc<Mutex<[Option<Instant>; LOCK_KEYS.len()]>>;
+
+/// The error a watcher stopped with, until it is taken from its handle.
+type WatchError = Arc<Mutex<Option<io::Error>>>;
+
+/// The mechanism used by a watcher to detect the lock key changes.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WatchMechanism {
+    /// Reads the lock key states at every watch interval.
+    Polling,
+    /// Waits for the [XKB](https://en.wikipedia.org/wiki/X_keyboard_extension) state notify events of the X server.
+    XkbStateNotify,
+    /// Reads the `EV_LED` events of a Linux keyboard event device.
+    EvdevLed,
+    /// Waits for the lock key events of a Windows low-level keyboard hook.
+    LowLevelKeyboardHook,
+    /// Waits for the lock key values of an `IOHIDManager` input value callback on the macOS keyboards.
+    IoHidValue,
+}
+
+/// The options of a lock key watcher, e.g. `WatchOptions::new().interval(Duration::from_millis(100))`.
+#[derive(Clone, Copy, Debug)]
+pub struct WatchOptions {
+    interval: Duration,
+    coalesce_window: Duration,
+    debounce_key_repeat: bool,
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        WatchOptions {
+            interval: Duration::from_millis(25),
+            coalesce_window: Duration::ZERO,
+            debounce_key_repeat: false,
+        }
+    }
+}
+
+impl WatchOptions {
+    /// Creates the default watch options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the interval between the state reads of the polling mechanism, 25 ms by default.
+    ///
+    /// The default keeps the polling latency close to the native notifications, e.g. for screen readers announcing
+    /// the Capital Lock changes.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the quiet period the lock keys must stay unchanged before their changes are reported, zero by default.
+    ///
+    /// Changes within the window collapse into the net change, e.g. a key flipped twice is not reported at all.
+    pub fn coalesce_window(mut self, window: Duration) -> Self {
+        self.coalesce_window = window;
+        self
+    }
+
+    /// Sets whether the flapping of a held lock key repeating is debounced, false by default.
+    ///
+    /// The XKB watcher raises the coalesce window to the auto-repeat delay plus interval of the keyboard, so the
+    /// repeats of a held key collapse into its net change. The other mechanisms have no auto-repeat information and
+    /// keep the coalesce window.
+    pub fn debounce_key_repeat(mut self, debounce: bool) -> Self {
+        self.debounce_key_repeat = debounce;
+        self
+    }
+
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn debounces_key_repeat(&self) -> bool {
+        self.debounce_key_repeat
+    }
+
+    /// Raises the coalesce window to cover the key repeats of the auto-repeat delay and interval.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub(crate) fn with_key_repeat(mut self, delay: Duration, interval: Duration) -> Self {
+        self.coalesce_window = self.coalesce_window.max(delay + interval);
+        self
+    }
+}
+
+/// A handle to a background lock key watcher, which stops it when dropped.
+#[derive(Debug)]
+pub struct WatchHandle {
+    mechanism: WatchMechanism,
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    last_changes: LastChanges,
+    error: WatchError,
+}
+
+impl WatchHandle {
+    /// Retrieves the mechanism used to detect the lock key changes.
+    pub fn mechanism(&self) -> WatchMechanism {
+        self.mechanism
+    }
+
+    /// Retrieves the time the watcher observed the last change of the lock key, or `None` when it has not seen any.
+    pub fn last_change(&self, key: LockKeys) -> Option<Instant> {
+        self.last_changes
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)[key as usize]
+    }
+
+    /// Checks whether the watcher is still watching, i.e. its thread has not stopped on an error.
+    pub fn is_running(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+
+    /// Takes the error the watcher stopped with, e.g. its event device was unplugged or the connection to the X
+    /// server was lost, leaving `None` in its place.
+    pub fn take_error(&self) -> Option<io::Error> {
+        self.error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+
+    /// Stops the watcher, waiting for its thread to finish.
+    pub fn stop(self) {}
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl LockKey {
+    /// Watches the standard lock keys from a background thread, calling `f` with each changed key and its new state.
+    ///
+    /// The watcher opens its own OS handle using the options of this lock key object. It waits for the XKB state
+    /// notify events on Linux, the events of a low-level keyboard hook on Windows and the values of an `IOHIDManager`
+    /// input value callback on macOS, and polls wherever the native notification is not available (e.g. without the
+    /// macOS Input Monitoring permission), in which case a change is reported within the watch interval, 25 ms by
+    /// default. On Linux, when the watcher cannot open the display, it reads the `EV_LED` events of
+    /// the first readable keyboard event device instead.
+    ///
+    /// A watcher stopping on an error, e.g. the connection to the X server lost, keeps it for
+    /// `WatchHandle::take_error`.
+    pub fn watch<F>(&self, options: WatchOptions, f: F) -> io::Result<WatchHandle>
+    where
+        F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+    {
+        let builder = self.options.clone();
+        #[cfg(target_os = "linux")]
+        return crate::linux::watch_xkb_state_notify(builder, options, f);
+        #[cfg(target_os = "windows")]
+        return crate::windows::watch_keyboard_hook(builder, options, f);
+        #[cfg(target_os = "macos")]
+        return crate::macos::watch_io_hid_values(builder, options, f);
+        #[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+        return watch_polling(move || builder.build(), options, f);
+    }
+}
+
+impl LockKey {
+    /// Watches a single lock key with the default watch options, calling `f` with each of its new states and ignoring
+    /// the changes of the other keys.
+    ///
+    /// Each call starts its own watcher, see `watch`.
+    pub fn on_change<F>(&self, key: LockKeys, f: F) -> io::Result<WatchHandle>
+    where
+        F: FnMut(LockKeyState) + Send + 'static,
+    {
+        self.watch(WatchOptions::default(), key_filter(key, f))
+    }
+}
+
+/// Adapts a single lock key callback to a watcher one, dropping the changes of the other keys.
+pub(crate) fn key_filter<F>(key: LockKeys, mut f: F) -> impl FnMut(LockKeys, LockKeyState)
+where
+    F: FnMut(LockKeyState),
+{
+    move |changed, state| {
+        if changed == key {
+            f(state)
+        }
+    }
+}
+
+/// Spawns a watcher thread, returning once `worker` reports the mechanism it uses or its setup error.
+///
+/// The worker records the time of the changes it reports in the last changes shared with the handle. The error it
+/// stops with once running is kept for `WatchHandle::take_error`.
+pub(crate) fn spawn_watcher<W>(worker: W) -> io::Result<WatchHandle>
+where
+    W: FnOnce(Receiver<()>, Sender<io::Result<WatchMechanism>>, LastChanges) -> io::Result<()>
+        + Send
+        + 'static,
+{
+    let (stop, stop_receiver) = mpsc::channel();
+    let (ready, ready_receiver) = mpsc::channel();
+    let last_changes = LastChanges::default();
+    let worker_last_changes = last_changes.clone();
+    let error = WatchError::default();
+    let worker_error = error.clone();
+    let thread = thread::Builder::new()
+        .name("lock_keys-watch".to_string())
+        .spawn(move || {
+            if let Err(err) = worker(stop_receiver, ready, worker_last_changes) {
+                *worker_error.lock().unwrap_or_else(PoisonError::into_inner) = Some(err);
+            }
+        })?;
+    match ready_receiver.recv() {
+        Ok(Ok(mechanism)) => Ok(WatchHandle {
+            mechanism,
+            stop: Some(stop),
+            thread: Some(thread),
+            last_changes,
+            error,
+        }),
+        Ok(Err(err)) => {
+            let _ = thread.join();
+            Err(err)
+        }
+        Err(_) => {
+            let _ = thread.join();
+            Err(io::Error::other("lock key watcher thread exited"))
+        }
+    }
+}
+
+/// Watches the lock key object returned by `open` reading its states at every interval.
+#[cfg(any(
+    not(any(target_os = "linux", target_os = "windows", target_os = "macos")),
+    test
+))]
+pub(crate) fn watch_polling<T, O, F>(
+    open: O,
+    options: WatchOptions,
+    f: F,
+) -> io::Result<WatchHandle>
+where
+    T: LockKeyWrapper,
+    O: FnOnce() -> io::Result<T> + Send + 'static,
+    F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+{
+    spawn_watcher(move |stop, ready, last_changes| {
+        let lock_key = match open() {
+            Ok(lock_key) => lock_key,
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let mut notifier = match lock_key.states() {
+            Ok(states) => ChangeNotifier::new(states, options, last_changes, f),
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let _ = ready.send(Ok(WatchMechanism::Polling));
+        poll_changes(&lock_key, &mut notifier, options, &stop);
+        Ok(())
+    })
+}
+
+/// Reads the lock key states at every interval until the watcher is stopped.
+pub(crate) fn poll_changes<T, F>(
+    lock_key: &T,
+    notifier: &mut ChangeNotifier<F>,
+    options: WatchOptions,
+    stop: &Receiver<()>,
+) where
+    T: LockKeyWrapper,
+    F: FnMut(LockKeys, LockKeyState),
+{
+    while let Err(RecvTimeoutError::Timeout) = stop.recv_timeout(options.interval) {
+        notifier.update(lock_key);
+    }
+}
+
+/// Reports the lock key changes to the watcher callback, once they stayed unchanged for the coalesce window.
+pub(crate) struct ChangeNotifier<F> {
+    f: F,
+    coalesce_window: Duration,
+    reported: LockKeyStates,
+    states: LockKeyStates,
+    changed_at: Option<Instant>,
+    last_changes: LastChanges,
+}
+
+impl<F> ChangeNotifier<F>
+where
+    F: FnMut(LockKeys, LockKeyState),
+{
+    pub(crate) fn new(
+        states: LockKeyStates,
+        options: WatchOptions,
+        last_changes: LastChanges,
+        f: F,
+    ) -> Self {
+        ChangeNotifier {
+            f,
+            coalesce_window: options.coalesce_window,
+            reported: states,
+            states,
+            changed_at: None,
+            last_changes,
+        }
+    }
+
+    /// Reads the lock key states, reporting the changes whose coalesce window elapsed.
+    pub(crate) fn update<T: LockKeyWrapper>(&mut self, lock_key: &T) {
+        match lock_key.states() {
+            Ok(states) => self.observe(states),
+            Err(_) => self.flush(),
+        }
+    }
+
+    /// Records the lock key states observed by the watcher, reporting the changes whose coalesce window elapsed.
+    pub(crate) fn observe(&mut self, states: LockKeyStates) {
+        if states != self.states {
+            self.states = states;
+            self.changed_at = Some(Instant::now());
+        }
+        self.flush();
+    }
+
+    /// Reports the net changes since the last report once the states stayed unchanged for the coalesce window.
+    pub(crate) fn flush(&mut self) {
+        match self.changed_at {
+            Some(changed_at) if changed_at.elapsed() >= self.coalesce_window => {
+                for (key, _, state) in self.reported.diff(&self.states) {
+                    self.last_changes
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)[key as usize] = Some(changed_at);
+                    (self.f)(key, state);
+                }
+                self.reported = self.states;
+                self.changed_at = None;
+            }
+            _ => {}
+        }
+    }
+}
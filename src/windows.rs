@@ -1,12 +1,37 @@
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::os::raw::c_int;
 use std::ptr;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
 
-use winapi::shared::minwindef::BYTE;
+use winapi::shared::minwindef::{BYTE, DWORD, FALSE, LPARAM, LRESULT, UINT, WORD, WPARAM};
+use winapi::shared::ntdef::SHORT;
+use winapi::shared::windef::{HDESK, HWND};
+use winapi::shared::winerror::ERROR_ACCESS_DENIED;
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::sysinfoapi::GetTickCount;
+use winapi::um::winnt::GENERIC_ALL;
 use winapi::um::winuser::{
-    keybd_event, GetKeyState, KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, VK_CAPITAL, VK_NUMLOCK,
-    VK_SCROLL,
+    CallNextHookEx, CloseDesktop, GetKeyState, GetKeyboardLayout, GetKeyboardState,
+    GetLastInputInfo, GetThreadDesktop, MapVirtualKeyExW, MsgWaitForMultipleObjects, OpenDesktopW,
+    PeekMessageW, PostMessageW, SendInput, SetKeyboardState, SetThreadDesktop, SetWindowsHookExW,
+    UnhookWindowsHookEx, HC_ACTION, INPUT, INPUT_KEYBOARD, KBDLLHOOKSTRUCT, KEYBDINPUT,
+    KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, LASTINPUTINFO, MAPVK_VK_TO_VSC, MSG, PM_REMOVE,
+    QS_ALLINPUT, VK_CAPITAL, VK_INSERT, VK_NUMLOCK, VK_SCROLL, WH_KEYBOARD_LL, WM_KEYDOWN,
+    WM_KEYUP,
 };
 
-use crate::{LockKey, LockKeyResult, LockKeyState, LockKeyWrapper, LockKeys};
+use crate::watch::{self, WatchHandle, WatchMechanism, WatchOptions};
+use crate::{
+    check_supported, unsupported_key, LockKey, LockKeyBuilder, LockKeyResult, LockKeyState,
+    LockKeyStates, LockKeyWrapper, LockKeys, LOCK_KEYS, UNMAPPED_ON_LAYOUT,
+};
 
 #[doc(hidden)]
 #[macro_export]
@@ -16,26 +41,497 @@ macro_rules! lock_key_to_vkkey {
             LockKeys::CapitalLock => VK_CAPITAL,
             LockKeys::NumberLock => VK_NUMLOCK,
             LockKeys::ScrollingLock => VK_SCROLL,
+            LockKeys::Insert => VK_INSERT,
+            LockKeys::ShiftLock => return Err(unsupported_key($key, "no virtual key on Windows")),
         }
     };
 }
 
+/// How many times an injection blocked by a session transition (e.g. fast user switching) is retried.
+const INPUT_BLOCKED_RETRIES: u32 = 5;
+
+/// The delay between the retries of a blocked injection, the transitions usually resolving within a second.
+const INPUT_BLOCKED_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How `set` handles Scrolling Lock, which compact keyboards only provide as a function layer (Fn) key.
+///
+/// A function layer Scrolling Lock cannot be detected, the scan codes coming from the software layout rather than
+/// from the keyboard, so refusing it is left to `ReadOnly`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum ScrollLockMode {
+    /// Injects `VK_SCROLL`, unless the layout leaves it unmapped, where `set` fails with `Unsupported` as for the
+    /// other lock keys.
+    #[default]
+    Inject,
+    /// Only reads the Scrolling Lock state, `set` fails with `Unsupported` when it would send the key press.
+    ReadOnly,
+}
+
+impl LockKey {
+    pub(crate) fn open(options: LockKeyBuilder) -> io::Result<Self> {
+        Ok(LockKey {
+            handle: ptr::null_mut(),
+            options,
+            trace: None,
+        })
+    }
+
+    fn get_key_state(&self, key: LockKeys) -> LockKeyResult {
+        let key_state = unsafe { GetKeyState(lock_key_to_vkkey!(key)) };
+        Ok(is_toggled(key_state).into())
+    }
+
+    fn get_keyboard_state(&self) -> io::Result<LockKeyStates> {
+        let mut keyboard_state = [0 as BYTE; 256];
+        if unsafe { GetKeyboardState(keyboard_state.as_mut_ptr()) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut states = LockKeyStates::new();
+        for key in LockKeys::all() {
+            // The low-order bit of a virtual key state indicates whether it is toggled.
+            let key_state = keyboard_state[lock_key_to_vkkey!(key) as usize] & 1 != 0;
+            states.insert(key, key_state.into());
+        }
+        Ok(states)
+    }
+
+    /// Flips the toggle bit of the lock key in the key state table of the calling thread, without any key event.
+    fn toggle_keyboard_state(&self, key: LockKeys) -> io::Result<()> {
+        let vk = lock_key_to_vkkey!(key) as usize;
+        let mut keyboard_state = [0 as BYTE; 256];
+        unsafe {
+            if GetKeyboardState(keyboard_state.as_mut_ptr()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            keyboard_state[vk] ^= 1;
+            if SetKeyboardState(keyboard_state.as_mut_ptr()) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn send_key_press(&self, key: LockKeys) -> io::Result<()> {
+        self.send_key_events(key, self.options.key_event_delay)
+    }
+
+    /// Sends the lock key press and release events, `hold` apart.
+    fn send_key_events(&self, key: LockKeys, hold: Duration) -> io::Result<()> {
+        let vk = lock_key_to_vkkey!(key);
+        // Injecting a key missing from the layout (e.g. Scrolling Lock on laptops) may be taken as another key.
+        if !layout_has_vkkey(vk) {
+            return Err(unsupported_key(key, UNMAPPED_ON_LAYOUT));
+        }
+        if key == LockKeys::ScrollingLock
+            && self.options.scroll_lock_mode == ScrollLockMode::ReadOnly
+        {
+            return Err(unsupported_key(key, "Scrolling Lock is read-only"));
+        }
+        self.wait_for_input_idle()?;
+        let scan_code = scan_code(vk) as WORD;
+        let vk = vk as WORD;
+        let send = |flags| {
+            retry_blocked(
+                || send_key_input(vk, scan_code, flags),
+                INPUT_BLOCKED_RETRIES,
+                INPUT_BLOCKED_RETRY_DELAY,
+            )
+        };
+        send(KEYEVENTF_EXTENDEDKEY)?;
+        if !hold.is_zero() {
+            thread::sleep(hold);
+        }
+        send(KEYEVENTF_EXTENDEDKEY | KEYEVENTF_KEYUP).map_err(|err| key_up_error(key, err))
+    }
+}
+
+impl LockKey {
+    /// Toggles the lock key, directly in the key state table in the direct state only mode, or by a key press.
+    fn toggle_key(&self, key: LockKeys) -> io::Result<()> {
+        if self.options.direct_state_only {
+            let result = self.toggle_keyboard_state(key);
+            return self.trace(format_args!("SetKeyboardState({:?})", key), result);
+        }
+        let result = self.send_key_press(key);
+        self.trace(format_args!("SendInput({:?})", key), result)
+    }
+
+    /// Holds the lock key down for the duration, then releases it, emulating a key held by the user using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    ///
+    /// Unlike `set`, which only sends a key press when the state differs, this always sends the press and the
+    /// release `duration` apart, toggling the lock key for the duration. As on Linux, where the key is latched, the
+    /// previous state comes back afterwards, restored by a second key press.
+    pub fn hold(&self, key: LockKeys, duration: Duration) -> io::Result<()> {
+        check_supported(key)?;
+        if self.options.direct_state_only {
+            return Err(unsupported_key(key, "key event injection disabled"));
+        }
+        let result = self
+            .send_key_events(key, duration)
+            .and_then(|()| self.send_key_press(key));
+        self.trace(format_args!("SendInput({:?}, {:?})", key, duration), result)
+    }
+
+    /// Posts the lock key press and release messages (`WM_KEYDOWN`/`WM_KEYUP`) to the window using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    ///
+    /// Only the window's message processing sees the key, neither the global toggle state nor the keyboard LED change.
+    // `PostMessageW` validates the window handle, failing with `ERROR_INVALID_WINDOW_HANDLE`.
+    #[allow(clippy::not_unsafe_ptr_arg_deref)]
+    pub fn post_to_window(&self, window: HWND, key: LockKeys) -> io::Result<()> {
+        unsafe {
+            let vk = lock_key_to_vkkey!(key);
+            let scan_code = scan_code(vk) as LPARAM;
+            // Repeat count of 1 and scan code, plus the previous key state and transition state bits on release.
+            let key_down = 1 | (scan_code << 16);
+            let key_up = key_down | (1 << 30) | (1 << 31);
+            for (message, lparam) in [(WM_KEYDOWN, key_down), (WM_KEYUP, key_up)] {
+                if PostMessageW(window, message, vk as WPARAM, lparam) == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl LockKey {
+    /// Sets a new state for the lock key on the secure desktop (`Winlogon`), where the login screen and the UAC
+    /// prompts run, e.g. to have Number Lock on at the login screen.
+    ///
+    /// A normal process cannot reach the secure desktop, so this requires the `SYSTEM` account, e.g. from a service,
+    /// and fails with `PermissionDenied` otherwise. The calling thread is switched to the secure desktop for the
+    /// change, then back to its desktop, which fails when it owns windows or hooks, so it is best called from a
+    /// dedicated thread. Testing it needs a service running as `SYSTEM` while the secure desktop is shown.
+    pub fn set_on_secure_desktop(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
+        check_supported(key)?;
+        let result = self.with_secure_desktop(|| self.set(key, state));
+        self.trace(
+            format_args!("SetThreadDesktop(Winlogon, {:?}, {})", key, state),
+            result,
+        )
+    }
+
+    /// Runs `f` with the calling thread switched to the `Winlogon` desktop, restoring its desktop afterward.
+    fn with_secure_desktop(&self, f: impl FnOnce() -> LockKeyResult) -> LockKeyResult {
+        let name: Vec<u16> = "Winlogon\0".encode_utf16().collect();
+        unsafe {
+            let previous = GetThreadDesktop(GetCurrentThreadId());
+            if previous.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let desktop: HDESK = OpenDesktopW(name.as_ptr(), 0, 0, GENERIC_ALL);
+            if desktop.is_null() {
+                return Err(secure_desktop_error(io::Error::last_os_error()));
+            }
+            if SetThreadDesktop(desktop) == 0 {
+                let err = io::Error::last_os_error();
+                CloseDesktop(desktop);
+                return Err(err);
+            }
+            let result = f();
+            SetThreadDesktop(previous);
+            CloseDesktop(desktop);
+            result
+        }
+    }
+}
+
+/// Maps the error opening the secure desktop, an access denied error telling the process is not privileged.
+pub(crate) fn secure_desktop_error(err: io::Error) -> io::Error {
+    if err.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) {
+        return io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the secure desktop requires the SYSTEM account",
+        );
+    }
+    err
+}
+
+/// Maps the lock key to its virtual key.
+fn vkkey(key: LockKeys) -> io::Result<c_int> {
+    Ok(lock_key_to_vkkey!(key))
+}
+
+impl LockKey {
+    /// Waits until the user input has been idle for the configured window, see `LockKeyBuilder::wait_for_input_idle`.
+    fn wait_for_input_idle(&self) -> io::Result<()> {
+        if self.options.input_idle_window.is_zero() {
+            return Ok(());
+        }
+        while let Some(wait) = input_idle_wait(input_idle_time()?, self.options.input_idle_window) {
+            thread::sleep(wait);
+        }
+        Ok(())
+    }
+}
+
+/// Retrieves how long the user input (keyboard or mouse) has been idle using `GetLastInputInfo`.
+fn input_idle_time() -> io::Result<Duration> {
+    let mut info = LASTINPUTINFO {
+        cbSize: mem::size_of::<LASTINPUTINFO>() as UINT,
+        dwTime: 0,
+    };
+    if unsafe { GetLastInputInfo(&mut info) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The tick count wraps around every 49.7 days.
+    let idle = unsafe { GetTickCount() }.wrapping_sub(info.dwTime);
+    Ok(Duration::from_millis(idle.into()))
+}
+
+/// Computes how long to wait before injecting given how long the input has been idle, or `None` once the input has
+/// been idle for the whole window.
+pub(crate) fn input_idle_wait(idle: Duration, window: Duration) -> Option<Duration> {
+    window.checked_sub(idle).filter(|wait| !wait.is_zero())
+}
+
+/// The error of an injection blocked by a session transition, the only one `retry_blocked` retries.
+#[derive(Debug)]
+struct InputBlocked;
+
+impl fmt::Display for InputBlocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SendInput was blocked")
+    }
+}
+
+impl Error for InputBlocked {}
+
+/// Converts the last error of a failed `SendInput` into an I/O error, marking the blocked injections of the session
+/// transitions: no error code at all, or `ERROR_ACCESS_DENIED` while the input desktop is switching.
+pub(crate) fn send_input_error(raw_os_error: Option<i32>) -> io::Error {
+    match raw_os_error {
+        Some(0) | None => io::Error::other(InputBlocked),
+        Some(code) if code == ERROR_ACCESS_DENIED as i32 => {
+            io::Error::new(io::ErrorKind::PermissionDenied, InputBlocked)
+        }
+        Some(code) => io::Error::from_raw_os_error(code),
+    }
+}
+
+/// Checks whether the injection failed as blocked by a session transition.
+pub(crate) fn is_input_blocked(err: &io::Error) -> bool {
+    err.get_ref().is_some_and(|err| err.is::<InputBlocked>())
+}
+
+/// Injects a key event with `SendInput`, failing when the input is blocked, e.g. during fast user switching or a
+/// session lock transition.
+fn send_key_input(vk: WORD, scan_code: WORD, flags: DWORD) -> io::Result<()> {
+    unsafe {
+        let mut input: INPUT = mem::zeroed();
+        input.type_ = INPUT_KEYBOARD;
+        *input.u.ki_mut() = KEYBDINPUT {
+            wVk: vk,
+            wScan: scan_code,
+            dwFlags: flags,
+            time: 0,
+            dwExtraInfo: 0,
+        };
+        if SendInput(1, &mut input, mem::size_of::<INPUT>() as c_int) == 0 {
+            return Err(send_input_error(io::Error::last_os_error().raw_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// Tells the key-up event of the lock key failed after its key-down went through, so the key may be left held down
+/// system-wide until the next key event.
+pub(crate) fn key_up_error(key: LockKeys, err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!(
+            "{:?} may be left held down, its key-up failed: {}",
+            key, err
+        ),
+    )
+}
+
+/// Retries `send` while it is blocked by a session transition, up to `retries` times `delay` apart, failing with its
+/// last error.
+///
+/// Only the blocked injections are transient, any other error (e.g. an invalid parameter) is returned at once.
+pub(crate) fn retry_blocked(
+    mut send: impl FnMut() -> io::Result<()>,
+    retries: u32,
+    delay: Duration,
+) -> io::Result<()> {
+    let mut attempt = 0;
+    loop {
+        match send() {
+            Ok(()) => return Ok(()),
+            Err(err) if attempt == retries || !is_input_blocked(&err) => return Err(err),
+            Err(_) => {
+                attempt += 1;
+                thread::sleep(delay);
+            }
+        }
+    }
+}
+
+/// Checks whether a `GetKeyState` result tells the key is toggled.
+///
+/// Only the low-order bit is the toggle state, the high-order one tells whether the key is down, e.g. while Shift
+/// is held along with Capital Lock on layouts where Shift releases it, so the caps state must not depend on it.
+pub(crate) fn is_toggled(key_state: SHORT) -> bool {
+    key_state & 1 != 0
+}
+
+/// Maps the virtual key to its scan code in the keyboard layout active for the calling thread, zero when the layout
+/// does not have it.
+///
+/// On systems with several layouts the scan codes differ from the default layout ones, e.g. under AZERTY, so they
+/// are resolved against the active layout (`GetKeyboardLayout(0)`) rather than with `MapVirtualKeyW`.
+pub(crate) fn scan_code(vk: c_int) -> UINT {
+    unsafe { MapVirtualKeyExW(vk as _, MAPVK_VK_TO_VSC, GetKeyboardLayout(0)) }
+}
+
+/// Checks whether the active keyboard layout has the virtual key, i.e. whether it maps to a scan code.
+pub(crate) fn layout_has_vkkey(vk: c_int) -> bool {
+    scan_code(vk) != 0
+}
+
+/// How long the keyboard hook watcher waits for messages before checking whether it was stopped, in milliseconds.
+const WATCH_STOP_CHECK_TIMEOUT: DWORD = 50;
+
+thread_local! {
+    /// Whether the keyboard hook of the watcher thread saw a lock key event since the watcher last checked.
+    static LOCK_KEY_EVENT: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Records the lock key events for the watcher of the thread, passing every event on to the next hook.
+unsafe extern "system" fn lock_key_event_hook(
+    code: c_int,
+    w_param: WPARAM,
+    l_param: LPARAM,
+) -> LRESULT {
+    if code == HC_ACTION {
+        let event = &*(l_param as *const KBDLLHOOKSTRUCT);
+        if matches!(
+            event.vkCode as c_int,
+            VK_CAPITAL | VK_NUMLOCK | VK_SCROLL | VK_INSERT
+        ) {
+            LOCK_KEY_EVENT.with(|event| event.set(true));
+        }
+    }
+    CallNextHookEx(ptr::null_mut(), code, w_param, l_param)
+}
+
+/// Watches the lock keys with a low-level keyboard hook, falling back to polling when it cannot be installed.
+pub(crate) fn watch_keyboard_hook<F>(
+    builder: LockKeyBuilder,
+    options: WatchOptions,
+    f: F,
+) -> io::Result<WatchHandle>
+where
+    F: FnMut(LockKeys, LockKeyState) + Send + 'static,
+{
+    watch::spawn_watcher(move |stop, ready, last_changes| {
+        let lock_key = match LockKey::open(builder) {
+            Ok(lock_key) => lock_key,
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let mut notifier = match lock_key.states() {
+            Ok(states) => watch::ChangeNotifier::new(states, options, last_changes, f),
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        };
+        let hook = unsafe {
+            SetWindowsHookExW(
+                WH_KEYBOARD_LL,
+                Some(lock_key_event_hook),
+                GetModuleHandleW(ptr::null()),
+                0,
+            )
+        };
+        if hook.is_null() {
+            let _ = ready.send(Ok(WatchMechanism::Polling));
+            watch::poll_changes(&lock_key, &mut notifier, options, &stop);
+            return Ok(());
+        }
+        let _ = ready.send(Ok(WatchMechanism::LowLevelKeyboardHook));
+        // The hook runs before the system toggles the key, so the states are read on the next wake.
+        let mut pending = false;
+        while let Err(TryRecvError::Empty) = stop.try_recv() {
+            unsafe {
+                MsgWaitForMultipleObjects(
+                    0,
+                    ptr::null(),
+                    FALSE,
+                    WATCH_STOP_CHECK_TIMEOUT,
+                    QS_ALLINPUT,
+                );
+                // Retrieving the messages calls the hook.
+                let mut msg: MSG = mem::zeroed();
+                while PeekMessageW(&mut msg, ptr::null_mut(), 0, 0, PM_REMOVE) != 0 {}
+            }
+            if pending {
+                notifier.update(&lock_key);
+            } else {
+                notifier.flush();
+            }
+            pending = LOCK_KEY_EVENT.with(|event| event.replace(false));
+        }
+        unsafe { UnhookWindowsHookEx(hook) };
+        Ok(())
+    })
+}
+
+impl LockKeyBuilder {
+    /// Sets the delay between the key press and release events sent by `set`, zero by default.
+    ///
+    /// Some virtual machine keyboard drivers coalesce or drop events sent too fast, missing the toggle.
+    pub fn key_event_delay(mut self, delay: Duration) -> Self {
+        self.key_event_delay = delay;
+        self
+    }
+
+    /// Sets how long the user input must have been idle before `set` injects the key events, zero (no wait) by
+    /// default.
+    ///
+    /// Injecting a lock key while the user is typing may lose or reorder their input. The idle time is the one of
+    /// `GetLastInputInfo`, so mouse input also defers the injection, which waits as long as the input goes on.
+    pub fn wait_for_input_idle(mut self, window: Duration) -> Self {
+        self.input_idle_window = window;
+        self
+    }
+
+    /// Sets how `set` handles Scrolling Lock, `ScrollLockMode::Inject` by default.
+    ///
+    /// On compact keyboards Scrolling Lock is a function layer key, where an injected `VK_SCROLL` may be taken as
+    /// another key by the keyboard driver, so apps targeting them should use `ScrollLockMode::ReadOnly`.
+    pub fn scroll_lock_mode(mut self, mode: ScrollLockMode) -> Self {
+        self.scroll_lock_mode = mode;
+        self
+    }
+}
+
 impl LockKeyWrapper for LockKey {
     /// Creates a new lock key object using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser) for handling.
     fn new() -> Self {
         LockKey {
             handle: ptr::null_mut(),
+            options: LockKeyBuilder::default(),
+            trace: None,
         }
     }
 
+    /// Tries to create a new lock key object using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser) for handling.
+    fn try_new() -> io::Result<Self> {
+        Ok(Self::new())
+    }
+
     /// Sets a new state for the lock key using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    ///
+    /// As the key press toggles the lock key, its state is read first to send it only when the state differs.
     fn set(&self, key: LockKeys, state: LockKeyState) -> LockKeyResult {
-        unsafe {
-            let key = lock_key_to_vkkey!(key) as BYTE;
-            keybd_event(key, 0x45, KEYEVENTF_EXTENDEDKEY | 0, 0);
-            keybd_event(key, 0x45, KEYEVENTF_EXTENDEDKEY | KEYEVENTF_KEYUP, 0);
-        }
-        Ok(state)
+        check_supported(key)?;
+        let current = self.state(key)?;
+        self.set_known(key, current, state)
     }
 
     /// Enables the lock key using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
@@ -50,14 +546,54 @@ impl LockKeyWrapper for LockKey {
 
     /// Toggles the lock key state returning its previous state using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
     fn toggle(&self, key: LockKeys) -> LockKeyResult {
+        check_supported(key)?;
         let state = self.state(key)?;
-        self.set(key, state.toggle())?;
+        self.set_known(key, state, state.toggle())?;
         Ok(state)
     }
 
     /// Retrieves the lock key state using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
     fn state(&self, key: LockKeys) -> LockKeyResult {
-        let key_state = unsafe { GetKeyState(lock_key_to_vkkey!(key)) == 1 };
-        Ok(key_state.into())
+        check_supported(key)?;
+        let result = self.get_key_state(key);
+        self.trace(format_args!("GetKeyState({:?})", key), result)
+    }
+    /// Retrieves the states of the standard lock keys in a single call using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    ///
+    /// `GetKeyboardState` copies the whole thread key state table at once, so polling the states costs one call
+    /// instead of one `GetKeyState` per key.
+    fn states(&self) -> io::Result<LockKeyStates> {
+        let result = self.get_keyboard_state();
+        self.trace(format_args!("GetKeyboardState()"), result)
+    }
+    /// Retrieves the lock keys the platform handles and the active keyboard layout maps, using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    fn supported_keys(&self) -> Vec<LockKeys> {
+        LOCK_KEYS
+            .into_iter()
+            .filter(|&key| key.is_supported() && vkkey(key).is_ok_and(layout_has_vkkey))
+            .collect()
+    }
+
+    /// Toggles the lock key sending its key press without reading its state using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    fn toggle_blind(&self, key: LockKeys) -> io::Result<()> {
+        check_supported(key)?;
+        self.toggle_key(key)
+    }
+
+    /// Sets a new state for the lock key trusting `current` as its state using [winuser API](https://docs.microsoft.com/en-us/windows/win32/api/winuser).
+    ///
+    /// The key press is sent only when `current` differs from `desired`, without reading the state.
+    fn set_known(
+        &self,
+        key: LockKeys,
+        current: LockKeyState,
+        desired: LockKeyState,
+    ) -> LockKeyResult {
+        check_supported(key)?;
+        if current == desired {
+            return Ok(desired);
+        }
+        self.toggle_key(key)?;
+        Ok(desired)
     }
 }